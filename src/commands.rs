@@ -1,15 +1,16 @@
-use crate::CommBulk;
+use crate::comm_bulk::CommBulk;
+use crate::error::{Error, Result};
 use hex_literal::hex;
-use log::debug;
+use log::{debug, info};
 use serde::{Deserialize, Serialize};
 
-pub fn cmd_nmea_switch(comm: &mut CommBulk, flag: bool) {
+pub fn cmd_nmea_switch(comm: &mut CommBulk, flag: bool) -> Result<()> {
     debug!("Send cmd_nmea_switch");
     let mut command: Vec<u8> = hex!["930101"].to_vec();
 
     command.push(if flag { 0x03 } else { 0x00 }); // 120b needs 0x03. 120 needed 0x00 (untested)
 
-    comm.simple_cmd_eqresult(command, vec![]);
+    comm.simple_cmd_eqresult(command, vec![])
 }
 
 #[derive(strum_macros::Display, Debug, PartialEq)]
@@ -20,54 +21,57 @@ pub enum Model {
     Gt200e,
 }
 
-pub fn cmd_model(comm: &mut CommBulk) -> Model {
+pub fn cmd_model(comm: &mut CommBulk) -> Result<Model> {
     debug!("Send cmd_model");
     let command: Vec<u8> = hex!["9305040003019f"].to_vec();
 
-    let answer = comm.simple_cmd_return(command);
+    let answer = comm.simple_cmd_return(command)?;
 
     if answer.len() != 3 || answer[0] != 0xc2 || answer[1] != 0x20 {
-        panic!("Unexpected answer: {answer:02x?}");
+        return Err(unexpected_answer(&answer));
     }
 
     let model = answer[2];
     match model {
-        0x13 => Model::Gt100,
-        0x14 => Model::Gt200,
-        0x15 => Model::Gt120, // a and b version!
-        0x17 => Model::Gt200e,
-        _ => panic!("Unknown model code: {:02x}", answer[2]),
+        0x13 => Ok(Model::Gt100),
+        0x14 => Ok(Model::Gt200),
+        0x15 => Ok(Model::Gt120), // a and b version!
+        0x17 => Ok(Model::Gt200e),
+        _ => Err(Error::UnknownModel(model as u16)),
     }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct IdentificationJson {
-    manufacturer: String,
-    model: u16,
+    pub manufacturer: String,
+    pub model: u16,
     #[serde(rename = "DeviceID")]
-    device_id: String,
-    name: String,
+    pub device_id: String,
+    pub name: String,
     pub alias: String, // variable, can be changed by owner
-    serial_number: String,
+    pub serial_number: String,
     #[serde(rename = "HWVersion")]
-    hw_version: String,
+    pub hw_version: String,
     #[serde(rename = "FWVersion")]
-    fw_version: String,
+    pub fw_version: String,
     #[serde(rename = "SWVersion")]
-    sw_version: String,
-    description: String,
+    pub sw_version: String,
+    pub description: String,
 }
 
-pub fn cmd_identification(comm: &mut CommBulk, conf_orig_sw_meta: bool) -> IdentificationJson {
+pub fn cmd_identification(
+    comm: &mut CommBulk,
+    conf_orig_sw_meta: bool,
+) -> Result<IdentificationJson> {
     debug!("Send cmd_identification");
     let command: Vec<u8> = hex!["930a"].to_vec();
 
-    let answer = comm.simple_cmd_return(command);
+    let answer = comm.simple_cmd_return(command)?;
 
     if answer.len() != 17 {
-        panic!("Unexpected answer: {answer:02x?}");
+        return Err(unexpected_answer(&answer));
     }
 
     let serial = u32::from_le_bytes(answer[0..4].try_into().unwrap()); // was little endian in commands.cpp
@@ -88,7 +92,7 @@ pub fn cmd_identification(comm: &mut CommBulk, conf_orig_sw_meta: bool) -> Ident
 
     let modelname = match model {
         10 => "GT120B",
-        _ => panic!("Unknown model code: {model}"),
+        _ => return Err(Error::UnknownModel(model)),
     };
 
     let id_struct = IdentificationJson {
@@ -109,9 +113,9 @@ pub fn cmd_identification(comm: &mut CommBulk, conf_orig_sw_meta: bool) -> Ident
         // TODO downloader-version (this version) "igotu-gt120 1.2.3/linux https://github/link"
     };
 
-    println!("{}", serde_json::to_string_pretty(&id_struct).unwrap());
+    info!("{}", serde_json::to_string_pretty(&id_struct).unwrap());
 
-    id_struct
+    Ok(id_struct)
 }
 
 fn calculate_offset_from_count(b: u8, c: u8) -> u32 {
@@ -119,24 +123,24 @@ fn calculate_offset_from_count(b: u8, c: u8) -> u32 {
     out_shifted << 12
 }
 
-pub fn cmd_count(comm: &mut CommBulk) -> u32 {
+pub fn cmd_count(comm: &mut CommBulk) -> Result<u32> {
     debug!("Send cmd_count");
     let command: Vec<u8> = hex!["930b03001d"].to_vec();
 
-    let answer = comm.simple_cmd_return(command);
+    let answer = comm.simple_cmd_return(command)?;
 
     if answer.len() != 3 {
-        panic!("Unexpected answer: {answer:02x?}");
+        return Err(unexpected_answer(&answer));
     }
 
     let offset = calculate_offset_from_count(answer[1], answer[2]);
 
     debug!("count/offset: {offset}, {offset:06x}");
 
-    offset
+    Ok(offset)
 }
 
-pub fn cmd_set_time(comm: &mut CommBulk, time_us: u64) {
+pub fn cmd_set_time(comm: &mut CommBulk, time_us: u64) -> Result<()> {
     debug!("Send cmd_set_time");
     let mut command: Vec<u8> = hex!["9309"].to_vec();
 
@@ -145,10 +149,10 @@ pub fn cmd_set_time(comm: &mut CommBulk, time_us: u64) {
     command.extend(&time_us.to_le_bytes()[0..8]);
     command.extend(&time_s.to_le_bytes()[0..5]);
 
-    comm.simple_cmd_eqresult(command, vec![]);
+    comm.simple_cmd_eqresult(command, vec![])
 }
 
-pub fn cmd_read(comm: &mut CommBulk, pos: u32, size: u16) -> Vec<u8> {
+pub fn cmd_read(comm: &mut CommBulk, pos: u32, size: u16) -> Result<Vec<u8>> {
     debug!("Send cmd_read (size: {size:04x}  pos: {pos:06x}");
     let mut command: Vec<u8> = hex!["930507"].to_vec();
 
@@ -157,25 +161,84 @@ pub fn cmd_read(comm: &mut CommBulk, pos: u32, size: u16) -> Vec<u8> {
     command.push(0x03);
     command.extend(&pos.to_be_bytes()[1..4]);
 
-    let answer = comm.simple_cmd_return(command);
+    let answer = comm.simple_cmd_return(command)?;
 
     if answer.len() != size as usize {
-        panic!("Unexpected answer: {answer:02x?}");
+        return Err(Error::WrongPayloadSize {
+            declared: size as usize,
+            actual: answer.len(),
+        });
     }
-    answer
+    Ok(answer)
 }
 
-pub fn cmd_delete_reboot(comm: &mut CommBulk) {
+/*
+ * The read and model commands look like a pass-through to the SPI flash chip (0x03=read, 0x9f=jedec id):
+ *   93 05 <len> <answer size:2> <spi cmd size> <spi cmd...>
+ * The write commands below use the same scheme with 93 06 and the standard SPI flash opcodes.
+ * UNVERIFIED: there is no recording of the original software writing to the device yet
+ */
+
+pub fn cmd_read_status(comm: &mut CommBulk) -> Result<u8> {
+    debug!("Send cmd_read_status");
+    let command: Vec<u8> = hex!["93050400010105"].to_vec();
+
+    let answer = comm.simple_cmd_return(command)?;
+
+    if answer.len() != 1 {
+        return Err(unexpected_answer(&answer));
+    }
+    Ok(answer[0])
+}
+
+pub fn cmd_write_enable(comm: &mut CommBulk) -> Result<()> {
+    debug!("Send cmd_write_enable");
+    let command: Vec<u8> = hex!["93060400000106"].to_vec();
+
+    comm.simple_cmd_eqresult(command, vec![])
+}
+
+pub fn cmd_sector_erase(comm: &mut CommBulk, pos: u32) -> Result<()> {
+    debug!("Send cmd_sector_erase (pos: {pos:06x})");
+    let mut command: Vec<u8> = hex!["9306070000"].to_vec();
+
+    command.push(0x04);
+    command.push(0x20);
+    command.extend(&pos.to_be_bytes()[1..4]);
+
+    comm.simple_cmd_eqresult(command, vec![])
+}
+
+pub fn cmd_page_program(comm: &mut CommBulk, pos: u32, data: &[u8]) -> Result<()> {
+    debug!(
+        "Send cmd_page_program (size: {:04x}  pos: {pos:06x})",
+        data.len()
+    );
+    let mut command: Vec<u8> = hex!["930607"].to_vec();
+
+    command.extend(&(data.len() as u16).to_be_bytes());
+    command.push(0x04);
+    command.push(0x02);
+    command.extend(&pos.to_be_bytes()[1..4]);
+
+    comm.data_cmd_eqresult(command, data, vec![])
+}
+
+pub fn cmd_delete_reboot(comm: &mut CommBulk) -> Result<()> {
     debug!("Send cmd_delete_reboot");
     let command: Vec<u8> = hex!["9311020080"].to_vec();
 
-    comm.simple_cmd_oneway_devicereset(command);
+    comm.simple_cmd_oneway_devicereset(command)
+}
+
+fn unexpected_answer(answer: &[u8]) -> Error {
+    Error::UnexpectedAnswer(format!("{answer:02x?}"))
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::Intf;
+    use crate::intf::Intf;
 
     struct IntfMock {
         req: Vec<u8>,
@@ -190,17 +253,18 @@ mod tests {
     }
 
     impl Intf for IntfMock {
-        fn send_and_receive(&mut self, to_device: Vec<u8>) -> Vec<u8> {
+        fn send_and_receive(&mut self, to_device: Vec<u8>) -> Result<Vec<u8>> {
             assert_eq!(to_device, self.req);
-            return self.res.clone();
+            Ok(self.res.clone())
         }
 
-        fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) {
+        fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) -> Result<()> {
             assert_eq!(to_device, self.req);
             assert_eq!(self.res, Vec::<u8>::new());
+            Ok(())
         }
 
-        fn get_time_micros(&self) -> u64 {
+        fn get_time_micros(&self) -> Result<u64> {
             panic!("Not implemented");
         }
     }
@@ -213,7 +277,7 @@ mod tests {
         );
         let flag = true;
 
-        cmd_nmea_switch(&mut comm, flag);
+        cmd_nmea_switch(&mut comm, flag).unwrap();
     }
 
     #[test]
@@ -223,11 +287,49 @@ mod tests {
             hex!["93 00 03 c2 20 15 73"].to_vec(),
         );
 
-        let result = cmd_model(&mut comm);
+        let result = cmd_model(&mut comm).unwrap();
 
         assert_eq!(result, Model::Gt120);
     }
 
+    #[test]
+    fn cmd_model_unknown_model() {
+        let mut comm = new_mock(
+            hex!["93 05 04 00 03 01 9f 00 00 00 00 00 00 00 00 c1"].to_vec(),
+            hex!["93 00 03 c2 20 16 72"].to_vec(),
+        );
+
+        let result = cmd_model(&mut comm);
+
+        assert!(matches!(result, Err(Error::UnknownModel(0x16))));
+    }
+
+    #[test]
+    fn cmd_model_checksum_error() {
+        let mut comm = new_mock(
+            hex!["93 05 04 00 03 01 9f 00 00 00 00 00 00 00 00 c1"].to_vec(),
+            hex!["93 00 03 c2 20 15 74"].to_vec(),
+        );
+
+        let result = cmd_model(&mut comm);
+
+        assert!(matches!(
+            result,
+            Err(Error::ChecksumMismatch {
+                actual: 0x74,
+                expected: 0x73
+            })
+        ));
+    }
+
+    pub(crate) fn sample_identification() -> IdentificationJson {
+        let mut comm = new_mock(
+            hex!["93 0a 00 00 00 00 00 00 00 00 00 00 00 00 00 63"].to_vec(),
+            hex!["93 00 11 a6 23 63 0d 01 02 00 0a 4d 2f 66 0d 71 8c 18 00 02 10"].to_vec(),
+        );
+        cmd_identification(&mut comm, true).unwrap()
+    }
+
     #[test]
     fn cmd_identification_true_goodcase() {
         let mut comm = new_mock(
@@ -235,7 +337,7 @@ mod tests {
             hex!["93 00 11 a6 23 63 0d 01 02 00 0a 4d 2f 66 0d 71 8c 18 00 02 10"].to_vec(),
         );
 
-        let result = cmd_identification(&mut comm, true);
+        let result = cmd_identification(&mut comm, true).unwrap();
 
         assert_eq!(result.manufacturer, "");
         assert_eq!(result.model, 10);
@@ -256,7 +358,7 @@ mod tests {
             hex!["93 00 03 00 0f 2b 30"].to_vec(),
         );
 
-        let result = cmd_count(&mut comm);
+        let result = cmd_count(&mut comm).unwrap();
 
         assert_eq!(result, 0x7A000);
     }
@@ -270,7 +372,7 @@ mod tests {
 
         let time_us = 1753997870971000_u64;
 
-        cmd_set_time(&mut comm, time_us);
+        cmd_set_time(&mut comm, time_us).unwrap();
     }
 
     #[test]
@@ -282,11 +384,71 @@ mod tests {
 
         let size = 0x0008;
         let pos = 0x1fff80;
-        let result = cmd_read(&mut comm, pos, size);
+        let result = cmd_read(&mut comm, pos, size).unwrap();
 
         assert_eq!(result, hex!["11 22 3f 44 55 66 77 88"].to_vec());
     }
 
+    #[test]
+    fn cmd_read_wrong_size() {
+        let mut comm = new_mock(
+            hex!["93 05 07 00 08 04 03 1f ff 80 00 00 00 00 00 b4"].to_vec(),
+            hex!["93 00 07 11 22 3f 44 55 66 77 7e"].to_vec(),
+        );
+
+        let result = cmd_read(&mut comm, 0x1fff80, 0x0008);
+
+        assert!(matches!(
+            result,
+            Err(Error::WrongPayloadSize {
+                declared: 8,
+                actual: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn cmd_read_status_goodcase() {
+        let mut comm = new_mock(
+            hex!["93 05 04 00 01 01 05 00 00 00 00 00 00 00 00 5d"].to_vec(),
+            hex!["93 00 01 03 69"].to_vec(),
+        );
+
+        let result = cmd_read_status(&mut comm).unwrap();
+
+        assert_eq!(result, 0x03);
+    }
+
+    #[test]
+    fn cmd_write_enable_goodcase() {
+        let mut comm = new_mock(
+            hex!["93 06 04 00 00 01 06 00 00 00 00 00 00 00 00 5c"].to_vec(),
+            hex!["93 00 00 6d"].to_vec(),
+        );
+
+        cmd_write_enable(&mut comm).unwrap();
+    }
+
+    #[test]
+    fn cmd_sector_erase_goodcase() {
+        let mut comm = new_mock(
+            hex!["93 06 07 00 00 04 20 00 00 00 00 00 00 00 00 3c"].to_vec(),
+            hex!["93 00 00 6d"].to_vec(),
+        );
+
+        cmd_sector_erase(&mut comm, 0x000000).unwrap();
+    }
+
+    #[test]
+    fn cmd_page_program_goodcase() {
+        let mut comm = new_mock(
+            hex!["93 06 07 00 04 04 02 00 01 00 00 00 00 00 00 55 11 22 33 44"].to_vec(),
+            hex!["93 00 00 6d"].to_vec(),
+        );
+
+        cmd_page_program(&mut comm, 0x000100, &hex!["11 22 33 44"]).unwrap();
+    }
+
     #[test]
     fn cmd_delete_reboot_goodcase() {
         let mut comm = new_mock(
@@ -294,6 +456,6 @@ mod tests {
             hex![""].to_vec(),
         );
 
-        cmd_delete_reboot(&mut comm);
+        cmd_delete_reboot(&mut comm).unwrap();
     }
 }
@@ -0,0 +1,103 @@
+use crate::comm_bulk::CommBulk;
+use crate::commands::{
+    IdentificationJson, Model, cmd_count, cmd_delete_reboot, cmd_identification, cmd_model,
+    cmd_nmea_switch, cmd_read, cmd_set_time,
+};
+use crate::device_config::DeviceConfig;
+use crate::error::Result;
+use crate::gt120b_workflow::{
+    WorkflowConfig, cmdblock_identify, cmdblock_readconfig, cmdblock_set_alias,
+    cmdblock_writeconfig, workflow,
+};
+use crate::intf::Intf;
+use crate::intf_bulk::IntfBulk;
+use crate::intf_file::IntfFile;
+use std::path::Path;
+
+/**
+ Handle to one GT-120B logger. Wraps the framing of [`CommBulk`] and offers the device commands as typed methods
+*/
+pub struct Device {
+    comm: CommBulk,
+}
+
+impl Device {
+    pub fn new(intf: Box<dyn Intf>) -> Self {
+        Device {
+            comm: CommBulk::new(intf),
+        }
+    }
+
+    /// Connect to the first GT-120B found on USB
+    pub fn open_usb() -> Result<Self> {
+        Ok(Self::new(Box::new(IntfBulk::new()?)))
+    }
+
+    /// Simulate a device using a replay file instead of real hardware access
+    pub fn open_replay(file_name: String) -> Result<Self> {
+        Ok(Self::new(Box::new(IntfFile::new(file_name)?)))
+    }
+
+    /// Low level access, e.g. for running single commands of [`crate::commands`]
+    pub fn comm(&mut self) -> &mut CommBulk {
+        &mut self.comm
+    }
+
+    pub fn nmea_switch(&mut self, flag: bool) -> Result<()> {
+        cmd_nmea_switch(&mut self.comm, flag)
+    }
+
+    pub fn model(&mut self) -> Result<Model> {
+        cmd_model(&mut self.comm)
+    }
+
+    pub fn identification(&mut self, conf_orig_sw_meta: bool) -> Result<IdentificationJson> {
+        cmd_identification(&mut self.comm, conf_orig_sw_meta)
+    }
+
+    /// Offset of the first unused memory block
+    pub fn count(&mut self) -> Result<u32> {
+        cmd_count(&mut self.comm)
+    }
+
+    pub fn set_time(&mut self, time_us: u64) -> Result<()> {
+        cmd_set_time(&mut self.comm, time_us)
+    }
+
+    /// Read `size` bytes of flash memory at `pos`
+    pub fn read(&mut self, pos: u32, size: u16) -> Result<Vec<u8>> {
+        cmd_read(&mut self.comm, pos, size)
+    }
+
+    /// Delete all track data. The device reboots and reconnects afterwards
+    pub fn delete_reboot(&mut self) -> Result<()> {
+        cmd_delete_reboot(&mut self.comm)
+    }
+
+    /// Start of every session: model, offset of the first unused memory block and identification
+    pub fn identify(
+        &mut self,
+        conf_orig_sw_meta: bool,
+    ) -> Result<(Model, u32, IdentificationJson)> {
+        cmdblock_identify(&mut self.comm, conf_orig_sw_meta)
+    }
+
+    pub fn read_config(&mut self) -> Result<DeviceConfig> {
+        cmdblock_readconfig(&mut self.comm)
+    }
+
+    /// Write the configuration block, after saving the old one to `backup_file`. EXPERIMENTAL
+    pub fn write_config(&mut self, config: &DeviceConfig, backup_file: &Path) -> Result<()> {
+        cmdblock_writeconfig(&mut self.comm, config, backup_file)
+    }
+
+    /// Change the alias and confirm it by reading the config again. The old config is saved to `backup_file`
+    pub fn set_alias(&mut self, alias: &str, backup_file: &Path) -> Result<DeviceConfig> {
+        cmdblock_set_alias(&mut self.comm, alias, backup_file)
+    }
+
+    /// Run a full download session, see [`workflow`]
+    pub fn download(&mut self, conf: &WorkflowConfig) -> Result<()> {
+        workflow(&mut self.comm, conf)
+    }
+}
@@ -0,0 +1,351 @@
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::fmt;
+
+/// Address of the configuration block in the device memory
+pub const CONFIG_POS: u32 = 0x000000;
+/// Size of the configuration block, as read by the original software
+pub const CONFIG_SIZE: u16 = 0x00ea;
+
+const POS_SMART_TRACKING_SPEED: usize = 2;
+/// Holds the same value as [`POS_SMART_TRACKING_SPEED`] in all dumps seen so far, so both are written
+const POS_SMART_TRACKING_SPEED_COPY: usize = 11;
+const POS_LOG_INTERVAL: usize = 4;
+const POS_SMART_TRACKING_INTERVAL: usize = 8;
+const POS_ALIAS: usize = 16;
+const ALIAS_SIZE: usize = 32;
+
+/**
+ Decoded configuration block of the device.
+
+ Only the fields with known meaning are decoded. The schedule/timer and button settings of the original software are
+ not located yet, only one block (firmware 1.2.231013) was analysed, and its bytes from 0x30 on were erased. The
+ complete block is kept in `raw`, so nothing gets lost and the unknown bytes can be shown for further protocol
+ analysis.
+*/
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DeviceConfig {
+    /// Logging interval in seconds
+    pub log_interval_s: u8,
+    /// Smart tracking: above this speed (km/h), `smart_tracking_interval_s` is used
+    pub smart_tracking_speed_kmh: u8,
+    /// Smart tracking: logging interval in seconds when moving faster than `smart_tracking_speed_kmh`
+    pub smart_tracking_interval_s: u8,
+    /// Name given by the owner, utf-8, 32 bytes zero padded
+    pub alias: String,
+    /// Complete configuration block as read from the device
+    #[serde(serialize_with = "serialize_hex")]
+    pub raw: Vec<u8>,
+}
+
+impl DeviceConfig {
+    pub fn from_bytes(raw: &[u8]) -> Result<Self> {
+        if raw.len() != CONFIG_SIZE as usize {
+            return Err(Error::WrongPayloadSize {
+                declared: CONFIG_SIZE as usize,
+                actual: raw.len(),
+            });
+        }
+        Ok(DeviceConfig {
+            log_interval_s: raw[POS_LOG_INTERVAL],
+            smart_tracking_speed_kmh: raw[POS_SMART_TRACKING_SPEED],
+            smart_tracking_interval_s: raw[POS_SMART_TRACKING_INTERVAL],
+            alias: Self::decode_alias(raw),
+            raw: raw.to_vec(),
+        })
+    }
+
+    /// Alias bytes which are erased (0xff) or not utf-8 are decoded lossy, as U+FFFD
+    fn decode_alias(raw: &[u8]) -> String {
+        let alias = String::from_utf8_lossy(&raw[POS_ALIAS..POS_ALIAS + ALIAS_SIZE]);
+        alias.trim_end_matches('\0').to_string()
+    }
+
+    /**
+     Serialises the config back into the block format. Unknown bytes are taken from `raw` unchanged. The alias bytes
+     too, unless the alias was changed: a lossy decoded alias isn't written back
+    */
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.raw.len() != CONFIG_SIZE as usize {
+            return Err(Error::InvalidConfig(format!(
+                "raw block has {} bytes instead of {}",
+                self.raw.len(),
+                CONFIG_SIZE
+            )));
+        }
+        let mut raw = self.raw.clone();
+        raw[POS_LOG_INTERVAL] = self.log_interval_s;
+        raw[POS_SMART_TRACKING_SPEED] = self.smart_tracking_speed_kmh;
+        raw[POS_SMART_TRACKING_SPEED_COPY] = self.smart_tracking_speed_kmh;
+        raw[POS_SMART_TRACKING_INTERVAL] = self.smart_tracking_interval_s;
+        if self.alias != Self::decode_alias(&self.raw) {
+            Self::check_alias(&self.alias)?;
+            let alias = self.alias.as_bytes();
+            raw[POS_ALIAS..POS_ALIAS + ALIAS_SIZE].fill(0);
+            raw[POS_ALIAS..POS_ALIAS + alias.len()].copy_from_slice(alias);
+        }
+        Ok(raw)
+    }
+
+    /// The alias must fit into 32 bytes utf-8 and must not contain `\0`, which is used for padding
+    pub fn check_alias(alias: &str) -> Result<()> {
+        if alias.len() > ALIAS_SIZE {
+            return Err(Error::InvalidConfig(format!(
+                "alias is {} bytes long (utf-8), maximum is {ALIAS_SIZE}",
+                alias.len()
+            )));
+        }
+        if alias.contains('\0') {
+            return Err(Error::InvalidConfig(
+                "alias must not contain \\0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+     Human readable list of differences between two configs, one line per changed field or byte
+    */
+    pub fn diff(&self, other: &DeviceConfig) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+        if self.alias != other.alias {
+            result.push(format!("alias: {:?} -> {:?}", self.alias, other.alias));
+        }
+        if self.log_interval_s != other.log_interval_s {
+            result.push(format!(
+                "log_interval_s: {} -> {}",
+                self.log_interval_s, other.log_interval_s
+            ));
+        }
+        if self.smart_tracking_speed_kmh != other.smart_tracking_speed_kmh {
+            result.push(format!(
+                "smart_tracking_speed_kmh: {} -> {}",
+                self.smart_tracking_speed_kmh, other.smart_tracking_speed_kmh
+            ));
+        }
+        if self.smart_tracking_interval_s != other.smart_tracking_interval_s {
+            result.push(format!(
+                "smart_tracking_interval_s: {} -> {}",
+                self.smart_tracking_interval_s, other.smart_tracking_interval_s
+            ));
+        }
+        let old = self.to_bytes()?;
+        let new = other.to_bytes()?;
+        for (pos, (o, n)) in old.iter().zip(new.iter()).enumerate() {
+            if o != n {
+                result.push(format!("  byte {pos:04x}: {o:02x} -> {n:02x}"));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Offsets of the bytes which are not decoded into any field
+    pub fn unknown_ranges() -> Vec<std::ops::Range<usize>> {
+        vec![
+            0..POS_SMART_TRACKING_SPEED,
+            POS_SMART_TRACKING_SPEED + 1..POS_LOG_INTERVAL,
+            POS_LOG_INTERVAL + 1..POS_SMART_TRACKING_INTERVAL,
+            POS_SMART_TRACKING_INTERVAL + 1..POS_SMART_TRACKING_SPEED_COPY,
+            POS_SMART_TRACKING_SPEED_COPY + 1..POS_ALIAS,
+            POS_ALIAS + ALIAS_SIZE..CONFIG_SIZE as usize,
+        ]
+    }
+}
+
+impl fmt::Display for DeviceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Alias:                     {}", self.alias)?;
+        writeln!(f, "Logging interval:          {}s", self.log_interval_s)?;
+        writeln!(
+            f,
+            "Smart tracking:            {}s above {}km/h",
+            self.smart_tracking_interval_s, self.smart_tracking_speed_kmh
+        )?;
+        writeln!(f, "Unknown bytes:")?;
+        for range in Self::unknown_ranges() {
+            if range.len() > 16 && self.raw[range.clone()].iter().all(|&b| b == 0xff) {
+                writeln!(f, "  {:04x}-{:04x}: erased", range.start, range.end - 1)?;
+                continue;
+            }
+            for chunk_start in range.clone().step_by(16) {
+                let chunk_end = (chunk_start + 16).min(range.end);
+                writeln!(
+                    f,
+                    "  {chunk_start:04x}: {}",
+                    hex_colon(&self.raw[chunk_start..chunk_end])
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex_colon(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn serialize_hex<S: serde::Serializer>(
+    data: &[u8],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config() -> Vec<u8> {
+        let mut raw = vec![0xffu8; CONFIG_SIZE as usize];
+        raw[0..16].copy_from_slice(&[
+            0x10, 0x0e, 0x0f, 0x00, 0x05, 0x00, 0x00, 0x02, 0x01, 0xd8, 0xff, 0x0f, 0x01, 0x06,
+            0x09, 0x21,
+        ]);
+        raw[16..48].fill(0);
+        raw[16..27].copy_from_slice(b"GT120B-0D66");
+        raw
+    }
+
+    #[test]
+    fn from_bytes_goodcase() {
+        let config = DeviceConfig::from_bytes(&sample_config()).unwrap();
+
+        assert_eq!(config.log_interval_s, 5);
+        assert_eq!(config.smart_tracking_speed_kmh, 15);
+        assert_eq!(config.smart_tracking_interval_s, 1);
+        assert_eq!(config.alias, "GT120B-0D66");
+        assert_eq!(config.raw, sample_config());
+    }
+
+    #[test]
+    fn from_bytes_wrong_size() {
+        let result = DeviceConfig::from_bytes(&[0u8; 0x10]);
+
+        assert!(matches!(
+            result,
+            Err(Error::WrongPayloadSize {
+                declared: 0xea,
+                actual: 0x10
+            })
+        ));
+    }
+
+    #[test]
+    fn to_bytes_roundtrip() {
+        let config = DeviceConfig::from_bytes(&sample_config()).unwrap();
+
+        assert_eq!(config.to_bytes().unwrap(), sample_config());
+    }
+
+    #[test]
+    fn to_bytes_changed_fields() {
+        let mut config = DeviceConfig::from_bytes(&sample_config()).unwrap();
+        config.log_interval_s = 10;
+        config.alias = "Rider 1".to_string();
+
+        let raw = config.to_bytes().unwrap();
+
+        assert_eq!(raw[4], 10);
+        assert_eq!(raw[16..48], [b"Rider 1".as_slice(), &[0u8; 25]].concat());
+        assert_eq!(raw[48..], sample_config()[48..]);
+        assert_eq!(
+            config
+                .diff(&DeviceConfig::from_bytes(&sample_config()).unwrap())
+                .unwrap()[0],
+            "alias: \"Rider 1\" -> \"GT120B-0D66\""
+        );
+    }
+
+    #[test]
+    fn to_bytes_smart_tracking_speed_both_bytes() {
+        let mut config = DeviceConfig::from_bytes(&sample_config()).unwrap();
+        config.smart_tracking_speed_kmh = 20;
+
+        let raw = config.to_bytes().unwrap();
+
+        assert_eq!((raw[2], raw[11]), (20, 20));
+        assert_eq!(
+            config
+                .diff(&DeviceConfig::from_bytes(&sample_config()).unwrap())
+                .unwrap()[1..],
+            ["  byte 0002: 14 -> 0f", "  byte 000b: 14 -> 0f"]
+        );
+    }
+
+    #[test]
+    fn to_bytes_alias_too_long() {
+        let mut config = DeviceConfig::from_bytes(&sample_config()).unwrap();
+        config.alias = "äöü-this-alias-is-too-long-for-32".to_string();
+
+        let result = config.to_bytes();
+
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn to_bytes_erased_alias_unchanged() {
+        let mut sample = sample_config();
+        sample[16..48].fill(0xff);
+        let mut config = DeviceConfig::from_bytes(&sample).unwrap();
+        config.log_interval_s = 10;
+
+        let raw = config.to_bytes().unwrap();
+
+        assert_eq!(config.alias, "\u{fffd}".repeat(32));
+        assert_eq!(raw[4], 10);
+        assert_eq!(raw[16..48], [0xff; 32]);
+    }
+
+    #[test]
+    fn to_bytes_invalid_utf8_alias_changed() {
+        let mut sample = sample_config();
+        sample[16..19].copy_from_slice(&[b'G', 0xc3, b'T']);
+        let mut config = DeviceConfig::from_bytes(&sample).unwrap();
+        assert_eq!(config.to_bytes().unwrap(), sample);
+        config.alias = "Rider 1".to_string();
+
+        let raw = config.to_bytes().unwrap();
+
+        assert_eq!(raw[16..48], [b"Rider 1".as_slice(), &[0u8; 25]].concat());
+    }
+
+    #[test]
+    fn check_alias_length_in_bytes() {
+        assert!(DeviceConfig::check_alias("").is_ok());
+        assert!(DeviceConfig::check_alias(&"a".repeat(32)).is_ok());
+        assert!(DeviceConfig::check_alias(&"a".repeat(33)).is_err());
+        assert!(DeviceConfig::check_alias(&"ä".repeat(16)).is_ok());
+        assert!(DeviceConfig::check_alias(&"ä".repeat(17)).is_err());
+        assert!(DeviceConfig::check_alias("a\0b").is_err());
+    }
+
+    #[test]
+    fn display_erased_range_as_one_line() {
+        let config = DeviceConfig::from_bytes(&sample_config()).unwrap();
+
+        let text = config.to_string();
+
+        assert!(text.contains("  0030-00e9: erased\n"), "{text}");
+        assert!(text.contains("  0000: 10:0e\n"), "{text}");
+    }
+
+    #[test]
+    fn unknown_ranges_dont_overlap_known_fields() {
+        let ranges = DeviceConfig::unknown_ranges();
+        let covered: usize = ranges.iter().map(|r| r.len()).sum();
+
+        assert_eq!(covered + 4 + ALIAS_SIZE, CONFIG_SIZE as usize);
+        for pos in [
+            POS_SMART_TRACKING_SPEED,
+            POS_SMART_TRACKING_SPEED_COPY,
+            POS_LOG_INTERVAL,
+            POS_SMART_TRACKING_INTERVAL,
+            POS_ALIAS,
+        ] {
+            assert!(ranges.iter().all(|r| !r.contains(&pos)));
+        }
+    }
+}
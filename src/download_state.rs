@@ -0,0 +1,111 @@
+use crate::error::{Error, Result};
+use crate::file_name::OutputFile;
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// First memory block holding track data
+pub const FIRST_DATA_OFFSET: u32 = 0x1000;
+
+/**
+ What was downloaded from one device in previous runs
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeviceDownloadState {
+    /// Start of the last memory block read. It may have been incomplete, so it is read again next time
+    pub next_offset: u32,
+    /// Time of the last waypoint exported. Older records are skipped on the next run
+    pub last_waypoint_time: Option<DateTime<Utc>>,
+}
+
+impl Default for DeviceDownloadState {
+    fn default() -> Self {
+        DeviceDownloadState {
+            next_offset: FIRST_DATA_OFFSET,
+            last_waypoint_time: None,
+        }
+    }
+}
+
+/**
+ Local state storage for incremental downloads, keyed by device id (see `IdentificationJson::device_id`).
+ Stored as json file
+*/
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct DownloadState {
+    devices: BTreeMap<String, DeviceDownloadState>,
+}
+
+impl DownloadState {
+    /// Loads the state file. A missing file is the same as an empty state
+    pub fn load(file_name: &str) -> Result<Self> {
+        if !Path::new(file_name).exists() {
+            info!("State file {file_name} doesn't exist yet. Downloading everything");
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(file_name)?;
+        serde_json::from_str(&content)
+            .map_err(|err| Error::StateFile(format!("{file_name}: {err}")))
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<()> {
+        debug!("Writing state file {file_name}");
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|err| Error::StateFile(format!("{file_name}: {err}")))?;
+        let mut f = OutputFile::create(Path::new(file_name))?;
+        f.write_all(content.as_bytes())?;
+        f.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, device_id: &str) -> DeviceDownloadState {
+        self.devices.get(device_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, device_id: &str, state: DeviceDownloadState) {
+        self.devices.insert(device_id.to_string(), state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn get_unknown_device() {
+        let state = DownloadState::default();
+
+        assert_eq!(
+            state.get("0010-00188C710D66"),
+            DeviceDownloadState::default()
+        );
+        assert_eq!(state.get("0010-00188C710D66").next_offset, 0x1000);
+    }
+
+    #[test]
+    fn save_load_roundtrip() {
+        let file_name = std::env::temp_dir()
+            .join(format!("igotu-state-test-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut state = DownloadState::default();
+        state.set(
+            "0010-00188C710D66",
+            DeviceDownloadState {
+                next_offset: 0x7a000,
+                last_waypoint_time: Some(Utc.with_ymd_and_hms(2025, 7, 31, 20, 8, 44).unwrap()),
+            },
+        );
+
+        state.save(&file_name).unwrap();
+        let loaded = DownloadState::load(&file_name).unwrap();
+        fs::remove_file(&file_name).unwrap();
+
+        assert_eq!(loaded, state);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::commands::Model;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Checksum error in answer. actual: {actual:02x}, expected: {expected:02x}")]
+    ChecksumMismatch { actual: u8, expected: u8 },
+
+    #[error("Invalid payload size. declared: {declared:02x}, actual: {actual:02x}")]
+    WrongPayloadSize { declared: usize, actual: usize },
+
+    #[error("Unexpected answer: {0}")]
+    UnexpectedAnswer(String),
+
+    #[error("Unknown model code: {0:02x}")]
+    UnknownModel(u16),
+
+    #[error("Unsupported model: {0}")]
+    UnsupportedModel(Model),
+
+    #[error(
+        "Unknown device state: {0:02x?}. needs more debugging/development, --tolerate-unknown-state downloads read-only"
+    )]
+    UnknownDeviceState(Vec<u8>),
+
+    #[error("Device state changed unexpectedly: {0}")]
+    StateMismatch(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Verification after writing failed: {0}")]
+    VerifyFailed(String),
+
+    #[error("Flash memory still busy: {0}")]
+    FlashBusy(String),
+
+    #[error("Cannot find device")]
+    DeviceNotFound,
+
+    #[error("USB timeout: {0}")]
+    UsbTimeout(String),
+
+    #[error("USB device disconnected: {0}")]
+    UsbDisconnected(String),
+
+    #[error("USB error: {0}")]
+    Usb(String),
+
+    #[error("SIMULATOR {0}")]
+    SimulatorMismatch(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid state file: {0}")]
+    StateFile(String),
+
+    #[error("Invalid raw dump: {0}")]
+    InvalidRawDump(String),
+
+    #[error("Written files don't match the downloaded data: {0}")]
+    ExportMismatch(String),
+
+    #[error("Undecodable data in strict mode: {0}")]
+    StrictParsing(String),
+}
+
+impl Error {
+    /**
+     Process exit code for the command line tool. Each error kind gets its own code, so wrapping scripts can react without parsing the output
+    */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ChecksumMismatch { .. } => 10,
+            Error::WrongPayloadSize { .. } => 11,
+            Error::UnexpectedAnswer(_) => 12,
+            Error::UnknownModel(_) => 13,
+            Error::UnsupportedModel(_) => 14,
+            Error::UnknownDeviceState(_) => 15,
+            Error::StateMismatch(_) => 16,
+            Error::InvalidConfig(_) => 17,
+            Error::VerifyFailed(_) => 18,
+            Error::FlashBusy(_) => 19,
+            Error::DeviceNotFound => 20,
+            Error::UsbTimeout(_) => 21,
+            Error::UsbDisconnected(_) => 22,
+            Error::Usb(_) => 23,
+            Error::SimulatorMismatch(_) => 30,
+            Error::Io(_) => 40,
+            Error::StateFile(_) => 41,
+            Error::InvalidRawDump(_) => 42,
+            Error::ExportMismatch(_) => 43,
+            Error::StrictParsing(_) => 44,
+        }
+    }
+}
+
+impl From<nusb::Error> for Error {
+    fn from(err: nusb::Error) -> Self {
+        match err.kind() {
+            nusb::ErrorKind::Disconnected => Error::UsbDisconnected(err.to_string()),
+            _ => Error::Usb(err.to_string()),
+        }
+    }
+}
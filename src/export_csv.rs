@@ -0,0 +1,120 @@
+use crate::gt120b_datadump::{ButtonEnum, DatablockEnum};
+use chrono::SecondsFormat;
+use std::io::{Result, Write};
+
+const HEADER: &str = "time,type,lat,lon,ele,speed,course,hdop,sat_used,sat_visib,wpflags,offset";
+
+/**
+ One row per record, including button presses and records which couldn't be decoded. Switch-off records have the
+ position of their last fix. With `switch_off_points`, they are `waypoint` rows like in the other formats, unless they
+ repeat the fix before. Fields not available for a record type are left empty.
+ `wpflags` are the flags computed from the surrounding button records, `offset` the flash offset of the record
+*/
+pub fn write_csv<'a, T: Write>(
+    f: &mut T,
+    records: impl Iterator<Item = (u32, &'a DatablockEnum)>,
+    switch_off_points: bool,
+) -> Result<()> {
+    writeln!(f, "{HEADER}")?;
+    let mut last_point_time = None;
+    for (offset, record) in records {
+        let time = record
+            .time_opt()
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            .unwrap_or_default();
+        match record {
+            DatablockEnum::Datablock(wpt) | DatablockEnum::SwitchOff(wpt) => writeln!(
+                f,
+                "{time},{},{},{},{},{},{},{},{},{},{},0x{offset:06x}",
+                match record {
+                    DatablockEnum::SwitchOff(_)
+                        if !switch_off_points || last_point_time == Some(wpt.time) =>
+                    {
+                        record_type(&ButtonEnum::Off)
+                    }
+                    _ => {
+                        last_point_time = Some(wpt.time);
+                        "waypoint"
+                    }
+                },
+                wpt.lat(),
+                wpt.lon(),
+                wpt.ele(),
+                wpt.speed,
+                wpt.course,
+                wpt.hdop,
+                wpt.sat_used,
+                wpt.sat_visib,
+                wpt.wpflags,
+            )?,
+            DatablockEnum::Button(_, typ, _) | DatablockEnum::ButtonWithoutTime(typ) => {
+                writeln!(f, "{time},{},,,,,,,,,,0x{offset:06x}", record_type(typ))?
+            }
+            DatablockEnum::WithoutPosition(..) => {
+                writeln!(f, "{time},no position,,,,,,,,,,0x{offset:06x}")?
+            }
+            DatablockEnum::Unknown { .. } => writeln!(f, ",unknown,,,,,,,,,,0x{offset:06x}")?,
+            DatablockEnum::NoBlock => {}
+        }
+    }
+    Ok(())
+}
+
+fn record_type(typ: &ButtonEnum) -> &'static str {
+    match typ {
+        ButtonEnum::On => "button on",
+        ButtonEnum::Off => "button off",
+        ButtonEnum::Trigger => "trigger",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{Waypoint, test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn csv_rows() {
+        let records = [
+            DatablockEnum::Button(
+                utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 7, 57, 457),
+                ButtonEnum::Trigger,
+                false,
+            ),
+            DatablockEnum::Datablock(Waypoint {
+                wpflags: 0x11,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441))
+            }),
+            DatablockEnum::ButtonWithoutTime(ButtonEnum::Off),
+            DatablockEnum::WithoutPosition(
+                utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 9, 0, 0),
+                true,
+            ),
+            DatablockEnum::Unknown {
+                offset: 0x1088,
+                raw: vec![0x13; 30],
+            },
+        ];
+        let mut buf = Vec::new();
+
+        write_csv(
+            &mut buf,
+            [0x1008, 0x1026, 0x1044, 0x106a, 0x1088]
+                .into_iter()
+                .zip(records.iter()),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "time,type,lat,lon,ele,speed,course,hdop,sat_used,sat_visib,wpflags,offset
+2025-07-31T20:07:57.457Z,trigger,,,,,,,,,,0x001008
+2025-07-31T20:08:44.441Z,waypoint,47.366684,8.548398,439.7,1.15,78.85,4.2,4,10,17,0x001026
+,button off,,,,,,,,,,0x001044
+2025-07-31T20:09:00Z,no position,,,,,,,,,,0x00106a
+,unknown,,,,,,,,,,0x001088
+"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use crate::gt120b_datadump::{Track, Waypoint};
+use chrono::{DateTime, Utc};
+use std::io::{Error, ErrorKind, Result, Write};
+
+/// FIT epoch 1989-12-31T00:00:00Z in unix seconds
+const FIT_EPOCH: i64 = 631065600;
+const PROFILE_VERSION: u16 = 2132;
+
+// global message numbers
+const MESG_FILE_ID: u16 = 0;
+const MESG_SESSION: u16 = 18;
+const MESG_LAP: u16 = 19;
+const MESG_RECORD: u16 = 20;
+const MESG_EVENT: u16 = 21;
+const MESG_ACTIVITY: u16 = 34;
+
+// field values
+const FILE_ACTIVITY: u8 = 4;
+const MANUFACTURER_DEVELOPMENT: u16 = 255;
+const EVENT_TIMER: u8 = 0;
+const EVENT_SESSION: u8 = 8;
+const EVENT_LAP: u8 = 9;
+const EVENT_ACTIVITY: u8 = 26;
+const EVENT_TYPE_START: u8 = 0;
+const EVENT_TYPE_STOP: u8 = 1;
+const EVENT_TYPE_STOP_ALL: u8 = 4;
+
+#[derive(Clone, Copy)]
+enum Field {
+    Enum(u8),
+    U16(u16),
+    U32(u32),
+    S32(i32),
+}
+
+impl Field {
+    fn base_type(&self) -> u8 {
+        match self {
+            Field::Enum(_) => 0x00,
+            Field::U16(_) => 0x84,
+            Field::U32(_) => 0x86,
+            Field::S32(_) => 0x85,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Field::Enum(v) => vec![*v],
+            Field::U16(v) => v.to_le_bytes().to_vec(),
+            Field::U32(v) => v.to_le_bytes().to_vec(),
+            Field::S32(v) => v.to_le_bytes().to_vec(),
+        }
+    }
+}
+
+/**
+ Encodes messages. Every global message type gets its own local message type, the definition is written before the
+ first data message
+*/
+struct FitEncoder {
+    data: Vec<u8>,
+    defined: Vec<u16>,
+}
+
+impl FitEncoder {
+    fn message(&mut self, global: u16, fields: &[(u8, Field)]) {
+        let local = match self.defined.iter().position(|&g| g == global) {
+            Some(local) => local as u8,
+            None => {
+                let local = self.defined.len() as u8;
+                self.defined.push(global);
+                // definition message, little endian
+                self.data.extend([0x40 | local, 0, 0]);
+                self.data.extend(global.to_le_bytes());
+                self.data.push(fields.len() as u8);
+                for (num, value) in fields {
+                    self.data
+                        .extend([*num, value.bytes().len() as u8, value.base_type()]);
+                }
+                local
+            }
+        };
+        self.data.push(local);
+        for (_, value) in fields {
+            self.data.extend(value.bytes());
+        }
+    }
+}
+
+/**
+ FIT activity file: file_id, timer events, one record per track point, a lap per track, one session and the activity.
+ An activity needs at least one track, an empty `tracks` is an error
+*/
+pub fn write_fit<T: Write>(f: &mut T, tracks: &[Track]) -> Result<()> {
+    if tracks.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "FIT: no tracks"));
+    }
+    let start = tracks[0].start_time();
+    let end = tracks[tracks.len() - 1].end_time();
+    let mut enc = FitEncoder {
+        data: Vec::new(),
+        defined: Vec::new(),
+    };
+
+    enc.message(
+        MESG_FILE_ID,
+        &[
+            (0, Field::Enum(FILE_ACTIVITY)),
+            (1, Field::U16(MANUFACTURER_DEVELOPMENT)),
+            (2, Field::U16(0)),
+            (4, Field::U32(fit_time(&start))),
+        ],
+    );
+
+    let mut total_timer_ms = 0;
+    let mut total_distance_m = 0.0;
+    for track in tracks {
+        event(&mut enc, &track.start_time(), EVENT_TYPE_START);
+        let mut distance_m = 0.0;
+        let mut prev: Option<&Waypoint> = None;
+        for wpt in track.points() {
+            if let Some(prev) = prev {
+                distance_m += prev.distance_to(wpt);
+            }
+            prev = Some(wpt);
+            enc.message(
+                MESG_RECORD,
+                &[
+                    (253, Field::U32(fit_time(&wpt.time))),
+                    (0, Field::S32(semicircles(wpt.lat_e7))),
+                    (1, Field::S32(semicircles(wpt.lon_e7))),
+                    (2, Field::U16(((wpt.ele() + 500.0) * 5.0).round() as u16)),
+                    (
+                        5,
+                        Field::U32(((total_distance_m + distance_m) * 100.0).round() as u32),
+                    ),
+                    (6, Field::U16((wpt.speed * 1000.0).round() as u16)),
+                ],
+            );
+        }
+        event(&mut enc, &track.end_time(), EVENT_TYPE_STOP_ALL);
+
+        let timer_ms = (track.end_time() - track.start_time()).num_milliseconds() as u32;
+        total_timer_ms += timer_ms;
+        total_distance_m += distance_m;
+        enc.message(
+            MESG_LAP,
+            &[
+                (253, Field::U32(fit_time(&track.end_time()))),
+                (2, Field::U32(fit_time(&track.start_time()))),
+                (7, Field::U32(timer_ms)),
+                (8, Field::U32(timer_ms)),
+                (9, Field::U32((distance_m * 100.0).round() as u32)),
+                (0, Field::Enum(EVENT_LAP)),
+                (1, Field::Enum(EVENT_TYPE_STOP)),
+            ],
+        );
+    }
+
+    enc.message(
+        MESG_SESSION,
+        &[
+            (253, Field::U32(fit_time(&end))),
+            (2, Field::U32(fit_time(&start))),
+            (7, Field::U32((end - start).num_milliseconds() as u32)),
+            (8, Field::U32(total_timer_ms)),
+            (9, Field::U32((total_distance_m * 100.0).round() as u32)),
+            (25, Field::U16(0)),
+            (26, Field::U16(tracks.len() as u16)),
+            (5, Field::Enum(0)), // sport: generic
+            (0, Field::Enum(EVENT_SESSION)),
+            (1, Field::Enum(EVENT_TYPE_STOP)),
+        ],
+    );
+    enc.message(
+        MESG_ACTIVITY,
+        &[
+            (253, Field::U32(fit_time(&end))),
+            (0, Field::U32(total_timer_ms)),
+            (1, Field::U16(1)),
+            (2, Field::Enum(0)), // type: manual
+            (3, Field::Enum(EVENT_ACTIVITY)),
+            (4, Field::Enum(EVENT_TYPE_STOP)),
+        ],
+    );
+
+    let mut header = vec![14, 0x10];
+    header.extend(PROFILE_VERSION.to_le_bytes());
+    header.extend((enc.data.len() as u32).to_le_bytes());
+    header.extend(b".FIT");
+    header.extend(crc(&header).to_le_bytes());
+
+    f.write_all(&header)?;
+    f.write_all(&enc.data)?;
+    f.write_all(&crc(&[header, enc.data].concat()).to_le_bytes())
+}
+
+/**
+ Decodes the message structure of a FIT file and counts the record messages (track points). Checks the header and
+ the file CRC. Used for verifying written files
+*/
+pub fn count_records(data: &[u8]) -> Result<usize> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("FIT: {msg}"));
+    let header_size = *data.first().ok_or_else(|| invalid("empty file"))? as usize;
+    if header_size < 12 || data.len() < header_size || &data[8..12] != b".FIT" {
+        return Err(invalid("invalid header"));
+    }
+    let data_size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    let end = header_size + data_size;
+    if data.len() != end + 2 {
+        return Err(invalid("wrong data size"));
+    }
+    if crc(&data[..end]).to_le_bytes() != data[end..] {
+        return Err(invalid("crc mismatch"));
+    }
+
+    // per local message type: global message number and size of the data message
+    let mut definitions: [Option<(u16, usize)>; 16] = [None; 16];
+    let mut records = 0;
+    let mut pos = header_size;
+    let byte = |pos: usize| data.get(pos).copied().ok_or_else(|| invalid("truncated"));
+    while pos < end {
+        let header = byte(pos)?;
+        pos += 1;
+        if header & 0x80 != 0 {
+            // compressed timestamp header, always a data message
+            let (global, size) = definitions[((header >> 5) & 0x03) as usize]
+                .ok_or_else(|| invalid("undefined local message"))?;
+            records += usize::from(global == MESG_RECORD);
+            pos += size;
+        } else if header & 0x40 != 0 {
+            let big_endian = byte(pos + 1)? == 1;
+            let global = [byte(pos + 2)?, byte(pos + 3)?];
+            let global = if big_endian {
+                u16::from_be_bytes(global)
+            } else {
+                u16::from_le_bytes(global)
+            };
+            let num_fields = byte(pos + 4)? as usize;
+            pos += 5;
+            let mut size = 0;
+            for i in 0..num_fields {
+                size += byte(pos + 3 * i + 1)? as usize;
+            }
+            pos += 3 * num_fields;
+            if header & 0x20 != 0 {
+                let num_dev_fields = byte(pos)? as usize;
+                pos += 1;
+                for i in 0..num_dev_fields {
+                    size += byte(pos + 3 * i + 1)? as usize;
+                }
+                pos += 3 * num_dev_fields;
+            }
+            definitions[(header & 0x0f) as usize] = Some((global, size));
+        } else {
+            let (global, size) = definitions[(header & 0x0f) as usize]
+                .ok_or_else(|| invalid("undefined local message"))?;
+            records += usize::from(global == MESG_RECORD);
+            pos += size;
+        }
+    }
+    if pos != end {
+        return Err(invalid("truncated message"));
+    }
+    Ok(records)
+}
+
+fn event(enc: &mut FitEncoder, time: &DateTime<Utc>, event_type: u8) {
+    enc.message(
+        MESG_EVENT,
+        &[
+            (253, Field::U32(fit_time(time))),
+            (0, Field::Enum(EVENT_TIMER)),
+            (1, Field::Enum(event_type)),
+        ],
+    );
+}
+
+fn fit_time(time: &DateTime<Utc>) -> u32 {
+    (time.timestamp() - FIT_EPOCH) as u32
+}
+
+/// from 1e-7 degrees
+fn semicircles(degrees_e7: i32) -> i32 {
+    (degrees_e7 as f64 * (2f64.powi(31) / 180e7)).round() as i32
+}
+
+/// CRC-16 as specified by the FIT protocol
+fn crc(data: &[u8]) -> u16 {
+    const CRC_TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    let mut crc = 0u16;
+    for byte in data {
+        for nibble in [byte & 0x0f, byte >> 4] {
+            let tmp = CRC_TABLE[(crc & 0x0f) as usize];
+            crc = (crc >> 4) & 0x0fff;
+            crc = crc ^ tmp ^ CRC_TABLE[nibble as usize];
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc(b"123456789"), 0xbb3d);
+    }
+
+    #[test]
+    fn fit_no_tracks() {
+        let mut buf = Vec::new();
+
+        let result = write_fit(&mut buf, &[]);
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn fit_header_and_crc() {
+        let wpt = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441));
+        let tracks = [Track {
+            segments: vec![vec![&wpt]],
+            trigger_times: vec![],
+            switch_on: None,
+            switch_off: None,
+        }];
+        let mut buf = Vec::new();
+
+        write_fit(&mut buf, &tracks).unwrap();
+
+        assert_eq!(&buf[8..12], b".FIT");
+        let data_size = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+        assert_eq!(buf.len(), 14 + data_size + 2);
+        assert_eq!(crc(&buf[..12]), u16::from_le_bytes([buf[12], buf[13]]));
+        // the crc over the whole file including the crc is 0
+        assert_eq!(crc(&buf), 0);
+        // record: timestamp, lat and lon in semicircles
+        let record = [
+            &fit_time(&wpt.time).to_le_bytes()[..],
+            &semicircles(473666840).to_le_bytes(),
+            &semicircles(85483980).to_le_bytes(),
+        ]
+        .concat();
+        assert!(buf.windows(record.len()).any(|w| w == record));
+        assert_eq!(count_records(&buf).unwrap(), 1);
+        buf[20] ^= 0xff;
+        assert!(count_records(&buf).is_err());
+    }
+}
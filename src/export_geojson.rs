@@ -0,0 +1,137 @@
+use crate::gt120b_datadump::{Track, Waypoint};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Value, json};
+use std::io::{Result, Write};
+
+/**
+ GeoJSON (RFC 7946) FeatureCollection with a LineString per track and a Point per button press.
+
+ The per-coordinate values of the tracks are arrays in the properties (`coordTimes` like in togeojson). The times of the
+ switch-on and switch-off records are `switchOnTime` and `switchOffTime`, if known
+*/
+pub fn write_geojson<T: Write>(
+    f: &mut T,
+    title: &str,
+    meta_desc: &str,
+    tracks: &[Track],
+) -> Result<()> {
+    let mut features: Vec<Value> = tracks
+        .iter()
+        .map(|track| track_feature(track, meta_desc))
+        .collect();
+    let triggers = tracks.iter().flat_map(|track| track.triggers());
+    for (i, (time, wpt)) in triggers.enumerate() {
+        features.push(json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": coordinate(wpt),
+            },
+            "properties": {
+                "name": format!("Button {}", i + 1),
+                "event": "trigger",
+                "time": format_time(&time),
+                "speed": num(wpt.speed),
+                "course": num(wpt.course),
+                "hdop": num(wpt.hdop),
+                "sat_used": wpt.sat_used,
+                "sat_visib": wpt.sat_visib,
+            },
+        }));
+    }
+
+    let collection = json!({
+        "type": "FeatureCollection",
+        "name": title,
+        "features": features,
+    });
+    serde_json::to_writer_pretty(&mut *f, &collection)?;
+    writeln!(f)
+}
+
+fn track_feature(track: &Track, meta_desc: &str) -> Value {
+    let points: Vec<&Waypoint> = track.points().collect();
+    let mut feature = json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "LineString",
+            "coordinates": points.iter().map(|wpt| coordinate(wpt)).collect::<Vec<_>>(),
+        },
+        "properties": {
+            "name": track.start_time().format("%Y-%m-%d_%H-%M").to_string(),
+            "desc": meta_desc,
+            "coordTimes": points.iter().map(|wpt| format_time(&wpt.time)).collect::<Vec<_>>(),
+            "speed": points.iter().map(|wpt| num(wpt.speed)).collect::<Vec<_>>(),
+            "course": points.iter().map(|wpt| num(wpt.course)).collect::<Vec<_>>(),
+            "hdop": points.iter().map(|wpt| num(wpt.hdop)).collect::<Vec<_>>(),
+            "sat_used": points.iter().map(|wpt| wpt.sat_used).collect::<Vec<_>>(),
+            "sat_visib": points.iter().map(|wpt| wpt.sat_visib).collect::<Vec<_>>(),
+        },
+    });
+    if let Some(time) = track.switch_on {
+        feature["properties"]["switchOnTime"] = format_time(&time).into();
+    }
+    if let Some(time) = track.switch_off {
+        feature["properties"]["switchOffTime"] = format_time(&time).into();
+    }
+    feature
+}
+
+fn coordinate(wpt: &Waypoint) -> Value {
+    json!([wpt.lon(), wpt.lat(), wpt.ele()])
+}
+
+/// f32 as json number with the same digits as in the other formats (a plain conversion to f64 adds noise digits)
+fn num(value: f32) -> Value {
+    json!(value.to_string().parse::<f64>().unwrap())
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn geojson_track_and_trigger() {
+        let wpt1 = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 0, 0));
+        let wpt2 = Waypoint {
+            lat_e7: 473667500,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 10, 0))
+        };
+        let tracks = [Track {
+            segments: vec![vec![&wpt1, &wpt2]],
+            trigger_times: vec![utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 1, 0)],
+            switch_on: None,
+            switch_off: None,
+        }];
+        let mut buf = Vec::new();
+
+        write_geojson(&mut buf, "2025-07-31_20-08", "desc", &tracks).unwrap();
+
+        let value: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(value["type"], "FeatureCollection");
+        let line = &value["features"][0];
+        assert_eq!(line["geometry"]["type"], "LineString");
+        assert_eq!(
+            line["geometry"]["coordinates"][1],
+            json!([8.548398, 47.36675, 439.7])
+        );
+        assert_eq!(
+            line["properties"]["coordTimes"],
+            json!(["2025-07-31T20:08:00Z", "2025-07-31T20:08:10Z"])
+        );
+        assert_eq!(line["properties"]["sat_visib"], json!([10, 10]));
+        let point = &value["features"][1];
+        assert_eq!(point["geometry"]["type"], "Point");
+        assert_eq!(
+            point["geometry"]["coordinates"],
+            json!([8.548398, 47.366684, 439.7])
+        );
+        assert_eq!(point["properties"]["time"], "2025-07-31T20:08:01Z");
+        assert_eq!(point["properties"]["hdop"], json!(4.2));
+    }
+}
@@ -0,0 +1,569 @@
+use crate::gt120b_datadump::{Track, Waypoint};
+use crate::xml::xml_escape;
+use chrono::{DateTime, SecondsFormat, Utc};
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::writer::{ElementWriter, Writer};
+use std::io::{Result, Write};
+
+/// Options for writing button presses as `<wpt>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ButtonWaypoints {
+    /// Symbol name, e.g. `Flag, Blue`
+    pub sym: String,
+    /// Write them into the track files
+    pub in_tracks: bool,
+    /// Write them to a separate file, independent of `in_tracks`
+    pub poi_file: bool,
+}
+
+/**
+ GPX 1.1 file header, including the opening of the first track segment. Mimics the output of the original software
+*/
+pub fn write_header<T: Write>(f: &mut T, meta_desc: &str) -> Result<()> {
+    write_start(f, meta_desc)?;
+    write_trk_start(f)
+}
+
+fn write_start<T: Write>(f: &mut T, meta_desc: &str) -> Result<()> {
+    writeln!(f,"﻿<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"no\"?>
+<!-- generated using test of rust implementation -->
+<gpx version=\"1.1\" creator=\"igotU_GPS_WIN\" xmlns:gpxx=\"http://www.garmin.com/xmlschemas/GpxExtensions/v3\" xmlns:gpxwpx=\"http://www.garmin.com/xmlschemas/WaypointExtension/v1\" xmlns:gpxtpx=\"http://www.garmin.com/xmlschemas/TrackPointExtension/v2\" xmlns:mat=\"http://www.mobileaction.com/xmlschemas/TrackPointExtension/v2\" xmlns=\"http://www.topografix.com/GPX/1/1\">
+  <metadata>
+    <desc>{}</desc>
+  </metadata>", xml_escape(meta_desc))
+}
+
+fn write_trk_start<T: Write>(f: &mut T) -> Result<()> {
+    writeln!(
+        f,
+        "  <trk>
+    <trkseg>"
+    )
+}
+
+/**
+ Complete GPX file with one `<trk>` per track and one `<trkseg>` per segment. Button presses are written as `<wpt>`
+ before the tracks if configured
+*/
+pub fn write_tracks<T: Write>(
+    f: &mut T,
+    meta_desc: &str,
+    tracks: &[Track],
+    button_waypoints: Option<&ButtonWaypoints>,
+) -> Result<()> {
+    write_start(f, meta_desc)?;
+    if let Some(button_waypoints) = button_waypoints {
+        write_button_wpts(f, tracks, &button_waypoints.sym)?;
+    }
+    write_trk_start(f)?;
+    for (i, track) in tracks.iter().enumerate() {
+        if i > 0 {
+            writeln!(
+                f,
+                "    </trkseg>
+  </trk>
+  <trk>
+    <trkseg>"
+            )?;
+        }
+        for (j, segment) in track.segments.iter().enumerate() {
+            if j > 0 {
+                writeln!(
+                    f,
+                    "    </trkseg>
+    <trkseg>"
+                )?;
+            }
+            for wpt in segment {
+                write_trkpt(f, wpt)?;
+            }
+        }
+    }
+    write_footer(f)
+}
+
+/**
+ GPX file with only the button presses as `<wpt>`
+*/
+pub fn write_poi<T: Write>(f: &mut T, meta_desc: &str, tracks: &[Track], sym: &str) -> Result<()> {
+    write_start(f, meta_desc)?;
+    write_button_wpts(f, tracks, sym)?;
+    writeln!(f, "</gpx>")
+}
+
+/// Button presses, numbered within the file and located at the nearest track point
+fn write_button_wpts<T: Write>(f: &mut T, tracks: &[Track], sym: &str) -> Result<()> {
+    let triggers = tracks.iter().flat_map(|track| track.triggers());
+    for (i, (time, wpt)) in triggers.enumerate() {
+        write_wpt(f, &format!("Button {}", i + 1), sym, &time, wpt)?;
+    }
+    Ok(())
+}
+
+pub fn write_wpt<T: Write>(
+    f: &mut T,
+    name: &str,
+    sym: &str,
+    time: &DateTime<Utc>,
+    position: &Waypoint,
+) -> Result<()> {
+    writeln!(
+        f,
+        "  <wpt lat=\"{}\" lon=\"{}\">
+    <ele>{}</ele>
+    <time>{}</time>
+    <name>{}</name>
+    <sym>{}</sym>
+    <type>Button</type>
+  </wpt>",
+        &position.lat(),
+        &position.lon(),
+        &position.ele(),
+        &time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        xml_escape(name),
+        xml_escape(sym),
+    )
+}
+
+pub fn write_footer<T: Write>(f: &mut T) -> Result<()> {
+    writeln!(
+        f,
+        "    </trkseg>
+  </trk>
+</gpx>"
+    )
+}
+
+pub fn write_trkpt<T: Write>(f: &mut T, wpt: &Waypoint) -> Result<()> {
+    writeln!(
+        f,
+        "      <trkpt lat=\"{}\" lon=\"{}\">
+        <ele>{}</ele>
+        <time>{}</time>{}
+        <sat>{}</sat>
+        <hdop>{}</hdop>
+        <extensions>
+          <gpxtpx:TrackPointExtension>
+            <gpxtpx:speed>{}</gpxtpx:speed>
+            <gpxtpx:course>{}</gpxtpx:course>
+          </gpxtpx:TrackPointExtension>
+          <mat:TrackPointExtension>
+            <mat:sat_view>{}</mat:sat_view>
+          </mat:TrackPointExtension>
+        </extensions>
+      </trkpt>",
+        &wpt.lat(),
+        &wpt.lon(),
+        &wpt.ele(),
+        &wpt.time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        if wpt.wpflags != 0 {
+            format!("\n        <type>WpFlag:{}</type>", &wpt.wpflags)
+        } else {
+            "".to_string()
+        },
+        &wpt.sat_used,
+        &wpt.hdop,
+        &wpt.speed,
+        &wpt.course,
+        &wpt.sat_visib,
+    )
+}
+
+/*
+ * Standard output: written with an escaping XML writer and a fixed number of decimals. The functions above are
+ * kept for output which is byte-compatible with the original software (`--orig-sw-gpx`)
+ */
+
+const GPX_NS: &str = "http://www.topografix.com/GPX/1/1";
+const GPXTPX_NS: &str = "http://www.garmin.com/xmlschemas/TrackPointExtension/v2";
+const MAT_NS: &str = "http://www.mobileaction.com/xmlschemas/TrackPointExtension/v2";
+const XSI_NS: &str = "http://www.w3.org/2001/XMLSchema-instance";
+const SCHEMA_LOCATION: &str = "http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd http://www.garmin.com/xmlschemas/TrackPointExtension/v2 http://www.garmin.com/xmlschemas/TrackPointExtensionv2.xsd";
+
+/// 1e-7 degrees, the resolution of the device
+const LATLON_DECIMALS: usize = 7;
+/// centimeters, cm/s and 0.01 degrees
+const ELE_SPEED_COURSE_DECIMALS: usize = 2;
+const HDOP_DECIMALS: usize = 1;
+
+/**
+ Complete GPX 1.1 file with one `<trk>` per track and one `<trkseg>` per segment. Button presses are written as
+ `<wpt>` before the tracks if configured, the switch-on and switch-off times as `<desc>` of the `<trk>`
+*/
+pub fn write_gpx<T: Write>(
+    f: &mut T,
+    meta_desc: &str,
+    tracks: &[Track],
+    button_waypoints: Option<&ButtonWaypoints>,
+) -> Result<()> {
+    let mut writer = Writer::new_with_indent(&mut *f, b' ', 2);
+    write_gpx_element(&mut writer, meta_desc, |w| {
+        if let Some(button_waypoints) = button_waypoints {
+            write_button_wpts_xml(w, tracks, &button_waypoints.sym)?;
+        }
+        for track in tracks {
+            w.create_element("trk").write_inner_content(|w| {
+                if let Some(desc) = switch_times_desc(track) {
+                    text_element(w, "desc", &desc)?;
+                }
+                for segment in &track.segments {
+                    w.create_element("trkseg").write_inner_content(|w| {
+                        for wpt in segment {
+                            write_trkpt_xml(w, wpt)?;
+                        }
+                        Ok(())
+                    })?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    })?;
+    writeln!(f)
+}
+
+/**
+ GPX file with only the button presses as `<wpt>`
+*/
+pub fn write_gpx_poi<T: Write>(
+    f: &mut T,
+    meta_desc: &str,
+    tracks: &[Track],
+    sym: &str,
+) -> Result<()> {
+    let mut writer = Writer::new_with_indent(&mut *f, b' ', 2);
+    write_gpx_element(&mut writer, meta_desc, |w| {
+        write_button_wpts_xml(w, tracks, sym)
+    })?;
+    writeln!(f)
+}
+
+/// Times of the switch-on and switch-off records, which have no track point of their own (without switch-off points)
+fn switch_times_desc(track: &Track) -> Option<String> {
+    let times: Vec<String> = [
+        ("switched on", track.switch_on),
+        ("switched off", track.switch_off),
+    ]
+    .into_iter()
+    .filter_map(|(what, time)| time.map(|time| format!("{what} {}", format_time(&time))))
+    .collect();
+    (!times.is_empty()).then(|| times.join(", "))
+}
+
+fn write_gpx_element<W: Write>(
+    writer: &mut Writer<W>,
+    meta_desc: &str,
+    content: impl FnOnce(&mut Writer<W>) -> Result<()>,
+) -> Result<()> {
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("gpx")
+        .with_attributes([
+            ("version", "1.1"),
+            (
+                "creator",
+                concat!("igotu-gt120b ", env!("CARGO_PKG_VERSION")),
+            ),
+            ("xmlns", GPX_NS),
+            ("xmlns:gpxtpx", GPXTPX_NS),
+            ("xmlns:mat", MAT_NS),
+            ("xmlns:xsi", XSI_NS),
+            ("xsi:schemaLocation", SCHEMA_LOCATION),
+        ])
+        .write_inner_content(|w| {
+            w.create_element("metadata")
+                .write_inner_content(|w| text_element(w, "desc", meta_desc))?;
+            content(w)
+        })?;
+    Ok(())
+}
+
+fn write_button_wpts_xml<W: Write>(w: &mut Writer<W>, tracks: &[Track], sym: &str) -> Result<()> {
+    let triggers = tracks.iter().flat_map(|track| track.triggers());
+    for (i, (time, wpt)) in triggers.enumerate() {
+        point_element(w, "wpt", wpt).write_inner_content(|w| {
+            text_element(
+                w,
+                "ele",
+                &format!("{:.*}", ELE_SPEED_COURSE_DECIMALS, wpt.ele()),
+            )?;
+            text_element(w, "time", &format_time(&time))?;
+            text_element(w, "name", &format!("Button {}", i + 1))?;
+            text_element(w, "sym", sym)?;
+            text_element(w, "type", "Button")
+        })?;
+    }
+    Ok(())
+}
+
+fn write_trkpt_xml<W: Write>(w: &mut Writer<W>, wpt: &Waypoint) -> Result<()> {
+    point_element(w, "trkpt", wpt).write_inner_content(|w| {
+        text_element(
+            w,
+            "ele",
+            &format!("{:.*}", ELE_SPEED_COURSE_DECIMALS, wpt.ele()),
+        )?;
+        text_element(w, "time", &format_time(&wpt.time))?;
+        if wpt.wpflags != 0 {
+            text_element(w, "type", &format!("WpFlag:{}", wpt.wpflags))?;
+        }
+        text_element(w, "sat", &wpt.sat_used.to_string())?;
+        text_element(w, "hdop", &format!("{:.*}", HDOP_DECIMALS, wpt.hdop))?;
+        w.create_element("extensions").write_inner_content(|w| {
+            w.create_element("gpxtpx:TrackPointExtension")
+                .write_inner_content(|w| {
+                    text_element(
+                        w,
+                        "gpxtpx:speed",
+                        &format!("{:.*}", ELE_SPEED_COURSE_DECIMALS, wpt.speed),
+                    )?;
+                    text_element(
+                        w,
+                        "gpxtpx:course",
+                        &format!("{:.*}", ELE_SPEED_COURSE_DECIMALS, wpt.course),
+                    )
+                })?;
+            w.create_element("mat:TrackPointExtension")
+                .write_inner_content(|w| {
+                    text_element(w, "mat:sat_view", &wpt.sat_visib.to_string())
+                })?;
+            Ok(())
+        })?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn point_element<'a, W: Write>(
+    w: &'a mut Writer<W>,
+    name: &'a str,
+    wpt: &Waypoint,
+) -> ElementWriter<'a, W> {
+    w.create_element(name)
+        .with_attribute(("lat", format!("{:.*}", LATLON_DECIMALS, wpt.lat()).as_str()))
+        .with_attribute(("lon", format!("{:.*}", LATLON_DECIMALS, wpt.lon()).as_str()))
+}
+
+fn text_element<W: Write>(w: &mut Writer<W>, name: &str, text: &str) -> Result<()> {
+    w.create_element(name)
+        .write_text_content(BytesText::new(text))?;
+    Ok(())
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{test_waypoint, utc_dt_from_ymd_hms_milli};
+    use std::io::BufWriter;
+
+    #[test]
+    fn dump() {
+        let input = Waypoint {
+            wpflags: 18,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441))
+        };
+        let buf = Vec::<u8>::new();
+        let mut writer = BufWriter::new(buf);
+
+        write_trkpt(&mut writer, &input).unwrap();
+
+        let s = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            s,
+            "      \
+      <trkpt lat=\"47.366684\" lon=\"8.548398\">
+        <ele>439.7</ele>
+        <time>2025-07-31T20:08:44.441Z</time>
+        <type>WpFlag:18</type>
+        <sat>4</sat>
+        <hdop>4.2</hdop>
+        <extensions>
+          <gpxtpx:TrackPointExtension>
+            <gpxtpx:speed>1.15</gpxtpx:speed>
+            <gpxtpx:course>78.85</gpxtpx:course>
+          </gpxtpx:TrackPointExtension>
+          <mat:TrackPointExtension>
+            <mat:sat_view>10</mat:sat_view>
+          </mat:TrackPointExtension>
+        </extensions>
+      </trkpt>
+"
+        );
+    }
+
+    #[test]
+    fn write_tracks_one_trk_per_track() {
+        let wpt = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441));
+        let tracks = [
+            Track {
+                segments: vec![vec![&wpt]],
+                trigger_times: vec![],
+                switch_on: None,
+                switch_off: None,
+            },
+            Track {
+                segments: vec![vec![&wpt], vec![&wpt]],
+                trigger_times: vec![],
+                switch_on: None,
+                switch_off: None,
+            },
+        ];
+        let mut buf = Vec::new();
+
+        write_tracks(&mut buf, "{\"Name\":\"<GT120B>\"}", &tracks, None).unwrap();
+
+        let s = String::from_utf8(buf).unwrap();
+        assert!(s.contains("<desc>{&quot;Name&quot;:&quot;&lt;GT120B&gt;&quot;}</desc>"));
+        assert_eq!(s.matches("<trk>").count(), 2);
+        assert_eq!(s.matches("</trk>").count(), 2);
+        assert_eq!(s.matches("<trkseg>").count(), 3);
+        assert_eq!(s.matches("</trkseg>").count(), 3);
+        assert_eq!(s.matches("<trkpt ").count(), 3);
+    }
+
+    #[test]
+    fn write_tracks_button_wpt() {
+        let wpt1 = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441));
+        let tracks = [Track {
+            segments: vec![vec![&wpt1]],
+            trigger_times: vec![utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 7, 57, 457)],
+            switch_on: None,
+            switch_off: None,
+        }];
+        let button_waypoints = ButtonWaypoints {
+            sym: "Flag, Blue".to_string(),
+            in_tracks: true,
+            poi_file: false,
+        };
+        let mut buf = Vec::new();
+
+        write_tracks(&mut buf, "desc", &tracks, Some(&button_waypoints)).unwrap();
+
+        let s = String::from_utf8(buf).unwrap();
+        assert!(s.contains(
+            "  </metadata>
+  <wpt lat=\"47.366684\" lon=\"8.548398\">
+    <ele>439.7</ele>
+    <time>2025-07-31T20:07:57.457Z</time>
+    <name>Button 1</name>
+    <sym>Flag, Blue</sym>
+    <type>Button</type>
+  </wpt>
+  <trk>
+"
+        ));
+    }
+
+    /**
+     Well-formedness, and validation with `xmllint` against the GPX 1.1 and TrackPointExtension v2 schemas in
+     `testdata/xsd`. The schema validation is skipped if `xmllint` isn't installed
+    */
+    fn validate_gpx(xml: &str) {
+        use quick_xml::Reader;
+        use std::process::Command;
+
+        let mut reader = Reader::from_str(xml);
+        while reader.read_event().unwrap() != Event::Eof {}
+
+        let file_name = std::env::temp_dir().join(format!(
+            "igotu-xsd-{}-{:?}.gpx",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&file_name, xml).unwrap();
+        let schema = format!(
+            "{}/testdata/xsd/gpx-with-extensions.xsd",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let output = Command::new("xmllint")
+            .args(["--noout", "--schema", &schema])
+            .arg(&file_name)
+            .output();
+        std::fs::remove_file(&file_name).unwrap();
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}\n{xml}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("xmllint not found, skipping the schema validation")
+            }
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    #[test]
+    fn write_gpx_schema() {
+        let wpt1 = Waypoint {
+            wpflags: 0x11,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441))
+        };
+        let tracks = [
+            Track {
+                segments: vec![vec![&wpt1]],
+                trigger_times: vec![utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 7, 57, 457)],
+                switch_on: None,
+                switch_off: None,
+            },
+            Track {
+                segments: vec![vec![&wpt1, &wpt1]],
+                trigger_times: vec![],
+                switch_on: Some(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 5, 5, 620)),
+                switch_off: Some(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 46, 441)),
+            },
+        ];
+        let button_waypoints = ButtonWaypoints {
+            sym: "Flag & <Pin>".to_string(),
+            in_tracks: true,
+            poi_file: false,
+        };
+        let mut buf = Vec::new();
+
+        write_gpx(
+            &mut buf,
+            "{\"Name\":\"<GT120B>\"}",
+            &tracks,
+            Some(&button_waypoints),
+        )
+        .unwrap();
+
+        let s = String::from_utf8(buf).unwrap();
+        validate_gpx(&s);
+        assert!(s.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx "));
+        assert!(s.contains("<desc>{&quot;Name&quot;:&quot;&lt;GT120B&gt;&quot;}</desc>"));
+        assert!(s.contains("<sym>Flag &amp; &lt;Pin&gt;</sym>"));
+        assert!(s.contains(
+            "      <trkpt lat=\"47.3666840\" lon=\"8.5483980\">
+        <ele>439.70</ele>
+        <time>2025-07-31T20:08:44.441Z</time>
+        <type>WpFlag:17</type>
+        <sat>4</sat>
+        <hdop>4.2</hdop>
+        <extensions>
+          <gpxtpx:TrackPointExtension>
+            <gpxtpx:speed>1.15</gpxtpx:speed>
+            <gpxtpx:course>78.85</gpxtpx:course>
+          </gpxtpx:TrackPointExtension>
+"
+        ));
+        assert!(s.contains(
+            "<desc>switched on 2025-07-31T20:05:05.620Z, switched off 2025-07-31T20:08:46.441Z</desc>"
+        ));
+        assert_eq!(s.matches("<trk>").count(), 2);
+    }
+
+    #[test]
+    fn write_gpx_poi_schema() {
+        let tracks = [];
+        let mut buf = Vec::new();
+
+        write_gpx_poi(&mut buf, "desc", &tracks, "Flag, Blue").unwrap();
+
+        validate_gpx(&String::from_utf8(buf).unwrap());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::gt120b_datadump::{Track, Waypoint};
+use crate::xml::xml_escape;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::{Result, Seek, Write};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const TRIGGER_ICON: &str = "https://maps.google.com/mapfiles/kml/paddle/red-stars.png";
+
+/**
+ KML 2.2 file for Google Earth with one Placemark per track and one per button press. Every segment is written as
+ LineString and additionally as gx:Track, which carries the timestamps for the time slider
+*/
+pub fn write_kml<T: Write>(
+    f: &mut T,
+    title: &str,
+    meta_desc: &str,
+    tracks: &[Track],
+) -> Result<()> {
+    writeln!(
+        f,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">
+  <Document>
+    <name>{}</name>
+    <description>{}</description>
+    <Style id=\"track\">
+      <LineStyle>
+        <color>ff0000ff</color>
+        <width>3</width>
+      </LineStyle>
+    </Style>
+    <Style id=\"trigger\">
+      <IconStyle>
+        <Icon>
+          <href>{TRIGGER_ICON}</href>
+        </Icon>
+      </IconStyle>
+    </Style>",
+        xml_escape(title),
+        xml_escape(meta_desc),
+    )?;
+    for track in tracks {
+        writeln!(
+            f,
+            "    <Placemark>
+      <name>{}</name>
+      <styleUrl>#track</styleUrl>
+      <MultiGeometry>",
+            track.start_time().format("%Y-%m-%d_%H-%M"),
+        )?;
+        for segment in &track.segments {
+            write_segment(f, segment)?;
+        }
+        writeln!(
+            f,
+            "      </MultiGeometry>
+    </Placemark>"
+        )?;
+    }
+    let triggers = tracks.iter().flat_map(|track| track.triggers());
+    for (i, (time, wpt)) in triggers.enumerate() {
+        write_trigger(f, i + 1, &time, wpt)?;
+    }
+    writeln!(
+        f,
+        "  </Document>
+</kml>"
+    )
+}
+
+/**
+ KMZ: the KML file as doc.kml inside a zip archive
+*/
+pub fn write_kmz<T: Write + Seek>(
+    f: &mut T,
+    title: &str,
+    meta_desc: &str,
+    tracks: &[Track],
+) -> Result<()> {
+    let mut zip = ZipWriter::new(f);
+    zip.start_file("doc.kml", SimpleFileOptions::default())?;
+    write_kml(&mut zip, title, meta_desc, tracks)?;
+    zip.finish()?;
+    Ok(())
+}
+
+fn write_segment<T: Write>(f: &mut T, segment: &[&Waypoint]) -> Result<()> {
+    writeln!(
+        f,
+        "        <LineString>
+          <tessellate>1</tessellate>
+          <altitudeMode>absolute</altitudeMode>
+          <coordinates>"
+    )?;
+    for wpt in segment {
+        writeln!(f, "            {},{},{}", wpt.lon(), wpt.lat(), wpt.ele())?;
+    }
+    writeln!(
+        f,
+        "          </coordinates>
+        </LineString>
+        <gx:Track>
+          <altitudeMode>absolute</altitudeMode>"
+    )?;
+    for wpt in segment {
+        writeln!(f, "          <when>{}</when>", format_time(&wpt.time))?;
+    }
+    for wpt in segment {
+        writeln!(
+            f,
+            "          <gx:coord>{} {} {}</gx:coord>",
+            wpt.lon(),
+            wpt.lat(),
+            wpt.ele()
+        )?;
+    }
+    writeln!(f, "        </gx:Track>")
+}
+
+fn write_trigger<T: Write>(
+    f: &mut T,
+    num: usize,
+    time: &DateTime<Utc>,
+    wpt: &Waypoint,
+) -> Result<()> {
+    writeln!(
+        f,
+        "    <Placemark>
+      <name>Button {num}</name>
+      <styleUrl>#trigger</styleUrl>
+      <TimeStamp>
+        <when>{}</when>
+      </TimeStamp>
+      <Point>
+        <altitudeMode>absolute</altitudeMode>
+        <coordinates>{},{},{}</coordinates>
+      </Point>
+    </Placemark>",
+        format_time(time),
+        wpt.lon(),
+        wpt.lat(),
+        wpt.ele(),
+    )
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn kml_track_and_trigger() {
+        let wpt1 = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 0, 0));
+        let wpt2 = Waypoint {
+            lat_e7: 473667500,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 10, 0))
+        };
+        let tracks = [Track {
+            segments: vec![vec![&wpt1, &wpt2]],
+            trigger_times: vec![utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 8, 0)],
+            switch_on: None,
+            switch_off: None,
+        }];
+        let mut buf = Vec::new();
+
+        write_kml(&mut buf, "2025-07-31_20-08", "{\"Name\":\"a&b\"}", &tracks).unwrap();
+
+        let s = String::from_utf8(buf).unwrap();
+        assert!(s.contains("<description>{&quot;Name&quot;:&quot;a&amp;b&quot;}</description>"));
+        assert!(s.contains(
+            "            8.548398,47.366684,439.7\n            8.548398,47.36675,439.7\n"
+        ));
+        assert!(s.contains(
+            "          <when>2025-07-31T20:08:00Z</when>\n          <when>2025-07-31T20:08:10Z</when>\n"
+        ));
+        assert!(s.contains("<gx:coord>8.548398 47.36675 439.7</gx:coord>"));
+        // button press at :08 is located at the nearest point (:10)
+        assert!(s.contains(
+            "      <name>Button 1</name>
+      <styleUrl>#trigger</styleUrl>
+      <TimeStamp>
+        <when>2025-07-31T20:08:08Z</when>
+      </TimeStamp>
+      <Point>
+        <altitudeMode>absolute</altitudeMode>
+        <coordinates>8.548398,47.36675,439.7</coordinates>"
+        ));
+        assert!(s.ends_with("  </Document>\n</kml>\n"));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::gt120b_datadump::{Track, Waypoint};
+use std::io::{Result, Write};
+
+const KNOTS_PER_MPS: f32 = 3600.0 / 1852.0;
+
+/**
+ NMEA 0183 log: GPRMC, GPGGA and GPVTG sentences for every track point
+*/
+pub fn write_nmea<T: Write>(f: &mut T, tracks: &[Track]) -> Result<()> {
+    for wpt in tracks.iter().flat_map(|track| track.points()) {
+        for sentence in sentences(wpt) {
+            write!(f, "{sentence}\r\n")?;
+        }
+    }
+    Ok(())
+}
+
+fn sentences(wpt: &Waypoint) -> [String; 3] {
+    let time = wpt.time.format("%H%M%S%.3f");
+    let date = wpt.time.format("%d%m%y");
+    let lat = format_coordinate(wpt.lat(), 2, 'N', 'S');
+    let lon = format_coordinate(wpt.lon(), 3, 'E', 'W');
+    let knots = wpt.speed * KNOTS_PER_MPS;
+    [
+        with_checksum(&format!(
+            "GPRMC,{time},A,{lat},{lon},{knots:.2},{:.2},{date},,,A",
+            wpt.course
+        )),
+        with_checksum(&format!(
+            "GPGGA,{time},{lat},{lon},1,{:02},{:.1},{:.1},M,,M,,",
+            wpt.sat_used,
+            wpt.hdop,
+            wpt.ele()
+        )),
+        with_checksum(&format!(
+            "GPVTG,{:.2},T,,M,{knots:.2},N,{:.2},K,A",
+            wpt.course,
+            wpt.speed * 3.6
+        )),
+    ]
+}
+
+/// `ddmm.mmmm,N` for latitudes (2 digits for degrees), `dddmm.mmmm,E` for longitudes
+fn format_coordinate(value: f64, degree_digits: usize, pos: char, neg: char) -> String {
+    let abs = value.abs();
+    // round first to avoid 60.0000 minutes
+    let total_minutes = (abs * 60.0 * 10000.0).round() / 10000.0;
+    let degrees = (total_minutes / 60.0).floor();
+    let minutes = total_minutes - degrees * 60.0;
+    format!(
+        "{:0degree_digits$}{:07.4},{}",
+        degrees as u32,
+        minutes,
+        if value < 0.0 { neg } else { pos }
+    )
+}
+
+fn with_checksum(body: &str) -> String {
+    let checksum = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    format!("${body}*{checksum:02X}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn checksum() {
+        // example from the NMEA 0183 documentation of gpsd
+        assert_eq!(
+            with_checksum(
+                "GPGGA,172814.0,3723.46587704,N,12202.26957864,W,2,6,1.2,18.893,M,-25.669,M,2.0,0031"
+            ),
+            "$GPGGA,172814.0,3723.46587704,N,12202.26957864,W,2,6,1.2,18.893,M,-25.669,M,2.0,0031*4F"
+        );
+    }
+
+    #[test]
+    fn coordinates() {
+        assert_eq!(format_coordinate(47.366684, 2, 'N', 'S'), "4722.0010,N");
+        assert_eq!(format_coordinate(-8.548398, 3, 'E', 'W'), "00832.9039,W");
+    }
+
+    #[test]
+    fn waypoint_sentences() {
+        let wpt = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441));
+
+        let result = sentences(&wpt);
+
+        assert_eq!(
+            result,
+            [
+                "$GPRMC,200844.441,A,4722.0010,N,00832.9039,E,2.24,78.85,310725,,,A*59",
+                "$GPGGA,200844.441,4722.0010,N,00832.9039,E,1,04,4.2,439.7,M,,M,,*4A",
+                "$GPVTG,78.85,T,,M,2.24,N,4.14,K,A*0A",
+            ]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::gt120b_datadump::Track;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::{Error, ErrorKind, Result, Write};
+
+/**
+ Garmin Training Center Database v2 file with one activity. Every track is a lap. An activity needs at least one
+ track, an empty `tracks` is an error
+*/
+pub fn write_tcx<T: Write>(f: &mut T, tracks: &[Track]) -> Result<()> {
+    if tracks.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "TCX: no tracks"));
+    }
+    writeln!(
+        f,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<TrainingCenterDatabase xmlns=\"http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2\" xmlns:ns3=\"http://www.garmin.com/xmlschemas/ActivityExtension/v2\">
+  <Activities>
+    <Activity Sport=\"Other\">
+      <Id>{}</Id>",
+        format_time(&tracks[0].start_time()),
+    )?;
+    for track in tracks {
+        write_lap(f, track)?;
+    }
+    writeln!(
+        f,
+        "    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"
+    )
+}
+
+fn write_lap<T: Write>(f: &mut T, track: &Track) -> Result<()> {
+    let max_speed = track.points().map(|wpt| wpt.speed).fold(0.0, f32::max);
+    writeln!(
+        f,
+        "      <Lap StartTime=\"{}\">
+        <TotalTimeSeconds>{}</TotalTimeSeconds>
+        <DistanceMeters>{:.1}</DistanceMeters>
+        <MaximumSpeed>{}</MaximumSpeed>
+        <Calories>0</Calories>
+        <Intensity>Active</Intensity>
+        <TriggerMethod>Manual</TriggerMethod>",
+        format_time(&track.start_time()),
+        (track.end_time() - track.start_time()).num_milliseconds() as f64 / 1000.0,
+        track.distance_m(),
+        max_speed,
+    )?;
+    for segment in &track.segments {
+        writeln!(f, "        <Track>")?;
+        let mut distance = 0.0;
+        for (i, wpt) in segment.iter().enumerate() {
+            if i > 0 {
+                distance += segment[i - 1].distance_to(wpt);
+            }
+            writeln!(
+                f,
+                "          <Trackpoint>
+            <Time>{}</Time>
+            <Position>
+              <LatitudeDegrees>{}</LatitudeDegrees>
+              <LongitudeDegrees>{}</LongitudeDegrees>
+            </Position>
+            <AltitudeMeters>{}</AltitudeMeters>
+            <DistanceMeters>{:.1}</DistanceMeters>
+            <Extensions>
+              <ns3:TPX>
+                <ns3:Speed>{}</ns3:Speed>
+              </ns3:TPX>
+            </Extensions>
+          </Trackpoint>",
+                format_time(&wpt.time),
+                wpt.lat(),
+                wpt.lon(),
+                wpt.ele(),
+                distance,
+                wpt.speed,
+            )?;
+        }
+        writeln!(f, "        </Track>")?;
+    }
+    writeln!(f, "      </Lap>")
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{Waypoint, test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn tcx_no_tracks() {
+        let mut buf = Vec::new();
+
+        let result = write_tcx(&mut buf, &[]);
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn tcx_lap_per_track() {
+        let wpt1 = Waypoint {
+            lat_e7: 470000000,
+            lon_e7: 85000000,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 0, 0, 0))
+        };
+        let wpt2 = Waypoint {
+            lat_e7: 470010000,
+            lon_e7: 85000000,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 1, 0, 0))
+        };
+        let wpt3 = Waypoint {
+            lat_e7: 470020000,
+            lon_e7: 85000000,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 30, 0, 0))
+        };
+        let tracks = [
+            Track {
+                segments: vec![vec![&wpt1, &wpt2]],
+                trigger_times: vec![],
+                switch_on: None,
+                switch_off: None,
+            },
+            Track {
+                segments: vec![vec![&wpt3]],
+                trigger_times: vec![],
+                switch_on: None,
+                switch_off: None,
+            },
+        ];
+        let mut buf = Vec::new();
+
+        write_tcx(&mut buf, &tracks).unwrap();
+
+        let s = String::from_utf8(buf).unwrap();
+        assert!(s.contains("<Id>2025-07-31T20:00:00Z</Id>"));
+        assert_eq!(s.matches("<Lap ").count(), 2);
+        assert!(s.contains(
+            "      <Lap StartTime=\"2025-07-31T20:00:00Z\">
+        <TotalTimeSeconds>60</TotalTimeSeconds>
+        <DistanceMeters>111.2</DistanceMeters>"
+        ));
+        assert!(s.contains("<Lap StartTime=\"2025-07-31T20:30:00Z\">"));
+        assert!(s.contains("<ns3:Speed>1.15</ns3:Speed>"));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::error::{Error, Result};
+use crate::export_fit;
+use crate::gt120b_datadump::{ExportFormat, ExportedFile};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+
+/**
+ Parses a written file again and counts the track points in it. Only the structure needed for counting is checked,
+ not the values
+*/
+pub fn count_points(path: &Path, format: ExportFormat) -> io::Result<usize> {
+    match format {
+        ExportFormat::Gpx => count_xml_elements(BufReader::new(File::open(path)?), b"trkpt"),
+        ExportFormat::Kml => count_xml_elements(BufReader::new(File::open(path)?), b"coord"),
+        ExportFormat::Kmz => {
+            let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+            let kml = zip.by_name("doc.kml")?;
+            count_xml_elements(BufReader::new(kml), b"coord")
+        }
+        ExportFormat::Tcx => count_xml_elements(BufReader::new(File::open(path)?), b"Trackpoint"),
+        ExportFormat::GeoJson => {
+            let value: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            let features = value["features"]
+                .as_array()
+                .ok_or_else(|| invalid("GeoJSON without features"))?;
+            Ok(features
+                .iter()
+                .filter(|feature| feature["geometry"]["type"] == "LineString")
+                .filter_map(|feature| feature["geometry"]["coordinates"].as_array())
+                .map(|coordinates| coordinates.len())
+                .sum())
+        }
+        ExportFormat::Csv => {
+            let mut count = 0;
+            for line in BufReader::new(File::open(path)?).lines().skip(1) {
+                if line?.split(',').nth(1) == Some("waypoint") {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        }
+        ExportFormat::Fit => export_fit::count_records(&fs::read(path)?),
+        ExportFormat::Nmea => {
+            let mut count = 0;
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !valid_nmea_checksum(&line) {
+                    return Err(invalid(&format!("NMEA checksum error: {line}")));
+                }
+                if line.starts_with("$GPRMC,") {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        }
+    }
+}
+
+/**
+ Re-parses all files of an export and compares the track points with the expected numbers, per file and in total.
+ `expected_total` is the number of track points decoded from the device memory. Files kept because they existed
+ already fail, their content can be from another run or device even if the number of points matches
+*/
+pub fn verify_export(files: &[ExportedFile], expected_total: usize) -> Result<()> {
+    if let Some(file) = files.iter().find(|file| !file.written) {
+        return Err(Error::ExportMismatch(format!(
+            "{}: existed already and was kept, it isn't verified to hold the downloaded data",
+            file.path.display()
+        )));
+    }
+    let mut total = 0;
+    for file in files {
+        let count = count_points(&file.path, file.format).map_err(|err| {
+            Error::ExportMismatch(format!("{}: cannot be parsed: {err}", file.path.display()))
+        })?;
+        if count != file.points {
+            return Err(Error::ExportMismatch(format!(
+                "{}: {count} track points instead of {}",
+                file.path.display(),
+                file.points
+            )));
+        }
+        total += count;
+    }
+    if total != expected_total {
+        return Err(Error::ExportMismatch(format!(
+            "{total} track points in all files, but {expected_total} were downloaded"
+        )));
+    }
+    Ok(())
+}
+
+fn count_xml_elements<R: Read>(reader: BufReader<R>, local_name: &[u8]) -> io::Result<usize> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    let mut count = 0;
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.local_name().as_ref() == local_name => {
+                count += 1
+            }
+            Ok(Event::Eof) => return Ok(count),
+            Ok(_) => {}
+            Err(err) => return Err(invalid(&err.to_string())),
+        }
+        buf.clear();
+    }
+}
+
+fn valid_nmea_checksum(line: &str) -> bool {
+    let Some((body, checksum)) = line
+        .strip_prefix('$')
+        .and_then(|sentence| sentence.split_once('*'))
+    else {
+        return false;
+    };
+    let actual = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    u8::from_str_radix(checksum, 16) == Ok(actual)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export_gpx::ButtonWaypoints;
+    use crate::gt120b_datadump::{Track, test_waypoint, utc_dt_from_ymd_hms_milli};
+    use crate::{export_csv, export_geojson, export_gpx, export_kml, export_nmea, export_tcx};
+    use std::fs::File;
+    use std::path::PathBuf;
+
+    #[test]
+    fn count_points_all_formats() {
+        let dir = std::env::temp_dir().join(format!("igotu-verify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wpt1 = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 0, 0));
+        let wpt2 = test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 10, 0));
+        let tracks = [Track {
+            segments: vec![vec![&wpt1, &wpt2]],
+            trigger_times: vec![utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 1, 0)],
+            switch_on: None,
+            switch_off: None,
+        }];
+        let button_waypoints = ButtonWaypoints {
+            sym: "Flag, Blue".to_string(),
+            in_tracks: true,
+            poi_file: false,
+        };
+        let write = |name: &str, format, write: &dyn Fn(&mut File) -> io::Result<()>| {
+            let path = dir.join(name);
+            write(&mut File::create(&path).unwrap()).unwrap();
+            (path, format)
+        };
+
+        let files: Vec<(PathBuf, ExportFormat)> = vec![
+            write("a.gpx", ExportFormat::Gpx, &|f| {
+                export_gpx::write_gpx(f, "desc", &tracks, Some(&button_waypoints))
+            }),
+            write("orig.gpx", ExportFormat::Gpx, &|f| {
+                export_gpx::write_tracks(f, "desc", &tracks, Some(&button_waypoints))
+            }),
+            write("a.kml", ExportFormat::Kml, &|f| {
+                export_kml::write_kml(f, "title", "desc", &tracks)
+            }),
+            write("a.kmz", ExportFormat::Kmz, &|f| {
+                export_kml::write_kmz(f, "title", "desc", &tracks)
+            }),
+            write("a.geojson", ExportFormat::GeoJson, &|f| {
+                export_geojson::write_geojson(f, "title", "desc", &tracks)
+            }),
+            write("a.tcx", ExportFormat::Tcx, &|f| {
+                export_tcx::write_tcx(f, &tracks)
+            }),
+            write("a.fit", ExportFormat::Fit, &|f| {
+                export_fit::write_fit(f, &tracks)
+            }),
+            write("a.nmea", ExportFormat::Nmea, &|f| {
+                export_nmea::write_nmea(f, &tracks)
+            }),
+        ];
+        for (path, format) in &files {
+            assert_eq!(
+                count_points(path, *format).unwrap(),
+                2,
+                "{}",
+                path.display()
+            );
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn verify_export_mismatch() {
+        let path = std::env::temp_dir().join(format!("igotu-verify-{}.csv", std::process::id()));
+        let wpt = crate::DatablockEnum::Datablock(test_waypoint(utc_dt_from_ymd_hms_milli(
+            2025, 7, 31, 20, 8, 0, 0,
+        )));
+        export_csv::write_csv(
+            &mut File::create(&path).unwrap(),
+            [(0x1000, &wpt)].into_iter(),
+            false,
+        )
+        .unwrap();
+        let mut files = vec![ExportedFile {
+            path: path.clone(),
+            format: ExportFormat::Csv,
+            points: 1,
+            written: true,
+        }];
+
+        assert!(verify_export(&files, 1).is_ok());
+        assert!(matches!(
+            verify_export(&files, 2),
+            Err(Error::ExportMismatch(_))
+        ));
+        files[0].points = 2;
+        assert!(matches!(
+            verify_export(&files, 2),
+            Err(Error::ExportMismatch(_))
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn verify_export_kept_file() {
+        let path =
+            std::env::temp_dir().join(format!("igotu-verify-kept-{}.csv", std::process::id()));
+        let wpt = crate::DatablockEnum::Datablock(test_waypoint(utc_dt_from_ymd_hms_milli(
+            2025, 7, 31, 20, 8, 0, 0,
+        )));
+        export_csv::write_csv(
+            &mut File::create(&path).unwrap(),
+            [(0x1000, &wpt)].into_iter(),
+            false,
+        )
+        .unwrap();
+        let files = vec![ExportedFile {
+            path: path.clone(),
+            format: ExportFormat::Csv,
+            points: 1,
+            written: false,
+        }];
+
+        // same number of points, but not written in this run
+        let result = verify_export(&files, 1);
+
+        fs::remove_file(path).unwrap();
+        assert!(matches!(result, Err(Error::ExportMismatch(_))));
+    }
+
+    #[test]
+    fn verify_export_csv_switch_off_points() {
+        let path =
+            std::env::temp_dir().join(format!("igotu-verify-off-{}.csv", std::process::id()));
+        let mut dump = crate::Gt120bDataDump::new(String::new(), String::new());
+        dump.set_file_names(
+            std::env::temp_dir(),
+            format!("igotu-verify-off-{}.{{ext}}", std::process::id())
+                .parse()
+                .unwrap(),
+            crate::file_name::CollisionPolicy::Overwrite,
+        );
+        dump.set_switch_off_points(true);
+        // switch on, fix, switch-off with a new fix, switch on, fix, switch-off repeating the fix
+        dump.process_datablock(0x001000, hex_literal::hex!["0c 00 41 02 5a 1c 39 e1
+            41 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            42 a6 19 07 fd 08 69 b5 2a 00 00 00 00 00 2c 95 3b 1c ce 55 18 05 b6 a3 00 00 19 00 c0 0f
+            41 a6 19 07 fd 09 10 27 2a 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            0c 00 41 02 5a 1c 39 e1
+            00 a4 19 07 fd 09 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            42 a4 19 07 fd 09 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff
+            ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff"]
+            .to_vec());
+
+        let files = dump
+            .write_out(crate::SplitPolicy::Single, ExportFormat::Csv, "")
+            .unwrap();
+
+        assert_eq!(dump.num_points(), 3);
+        assert_eq!(files[0].path, path);
+        assert!(verify_export(&files, dump.num_points()).is_ok());
+        let tracks = dump.tracks(false);
+        let flags: Vec<u8> = tracks
+            .iter()
+            .flat_map(|t| t.points())
+            .map(|w| w.wpflags)
+            .collect();
+        assert_eq!(flags, [0x01, 0x02, 0x03]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+use chrono::NaiveDateTime;
+use chrono::format::{Item, StrftimeItems};
+use log::{info, warn};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// Format of `{start}` and `{end}` without explicit format, also used by the original software
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d_%H-%M";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Alias,
+    Serial,
+    DeviceId,
+    Index,
+    Start(String),
+    End(String),
+    Distance,
+    Ext,
+}
+
+/**
+ Template for output file names, relative to the output directory. `/` creates sub directories.
+
+ Placeholders: `{alias}`, `{serial}`, `{device_id}`, `{index}` (number of the file in this download, from 1),
+ `{start}` and `{end}` (time of the first and last point, optionally with a chrono format like `{start:%Y/%m}`),
+ `{distance}` (km, 1 decimal) and `{ext}`. `{{` and `}}` are literal braces
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileNameTemplate {
+    parts: Vec<Part>,
+    /// rendered names must stay inside the output directory. Not for the legacy prefix, which can be any path
+    relative: bool,
+}
+
+impl FileNameTemplate {
+    /// `{prefix}{start}{suffix}.{ext}`, the naming of the original software
+    pub fn legacy(prefix: &str, suffix: &str) -> Self {
+        FileNameTemplate {
+            parts: vec![
+                Part::Literal(prefix.to_string()),
+                Part::Start(DEFAULT_TIME_FORMAT.to_string()),
+                Part::Literal(format!("{suffix}.")),
+                Part::Ext,
+            ],
+            relative: false,
+        }
+    }
+
+    /// Fails if the name would be absolute or leave the output directory with `..`
+    pub fn render(&self, values: &FileNameValues) -> Result<String> {
+        let name: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Alias => sanitize(&values.alias),
+                Part::Serial => sanitize(&values.serial),
+                Part::DeviceId => sanitize(&values.device_id),
+                Part::Index => values.index.to_string(),
+                Part::Start(format) => values.start.format(format).to_string(),
+                Part::End(format) => values.end.format(format).to_string(),
+                Part::Distance => format!("{:.1}", values.distance_m / 1000.0),
+                Part::Ext => values.ext.to_string(),
+            })
+            .collect();
+        let path = Path::new(&name);
+        if self.relative
+            && (path.has_root()
+                || path.is_absolute()
+                || path.components().any(|c| c == Component::ParentDir))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("file name {name} is outside of the output directory"),
+            ));
+        }
+        Ok(name)
+    }
+}
+
+impl FromStr for FileNameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let placeholder: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let (name, format) = match placeholder.split_once(':') {
+                        Some((name, format)) => (name, Some(format.to_string())),
+                        None => (placeholder.as_str(), None),
+                    };
+                    if let Some(format) = &format
+                        && StrftimeItems::new(format).any(|item| item == Item::Error)
+                    {
+                        return Err(format!("invalid time format in {{{placeholder}}}"));
+                    }
+                    let part = match (name, format) {
+                        ("alias", None) => Part::Alias,
+                        ("serial", None) => Part::Serial,
+                        ("device_id", None) => Part::DeviceId,
+                        ("index", None) => Part::Index,
+                        ("start", format) => {
+                            Part::Start(format.unwrap_or(DEFAULT_TIME_FORMAT.to_string()))
+                        }
+                        ("end", format) => {
+                            Part::End(format.unwrap_or(DEFAULT_TIME_FORMAT.to_string()))
+                        }
+                        ("distance", None) => Part::Distance,
+                        ("ext", None) => Part::Ext,
+                        _ => return Err(format!("unknown placeholder {{{placeholder}}}")),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                }
+                '}' => return Err("unmatched }, use }} for a literal brace".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(FileNameTemplate {
+            parts,
+            relative: true,
+        })
+    }
+}
+
+/// Values for the placeholders of a [`FileNameTemplate`]
+#[derive(Debug, Clone, Default)]
+pub struct FileNameValues {
+    pub alias: String,
+    pub serial: String,
+    pub device_id: String,
+    pub index: usize,
+    /// in the timezone of the file names
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub distance_m: f64,
+    pub ext: &'static str,
+}
+
+/// Device values are used as file or directory names, so path separators and characters invalid on Windows are replaced
+fn sanitize(value: &str) -> String {
+    if value.trim().is_empty() {
+        // an empty directory name would make `{alias}/...` absolute
+        return "unnamed".to_string();
+    }
+    if value.chars().all(|c| c == '.') {
+        // `.` and `..` are no names
+        return "_".repeat(value.len());
+    }
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// What to do if an output file exists already
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CollisionPolicy {
+    /// Replace the file, with a warning
+    #[default]
+    Overwrite,
+    /// Keep the existing file and don't write this one
+    Skip,
+    /// Write to a new name with `_1`, `_2`, ... before the extension
+    Suffix,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "skip" => Ok(CollisionPolicy::Skip),
+            "suffix" => Ok(CollisionPolicy::Suffix),
+            _ => Err(format!(
+                "unknown collision policy <{s}>, expected overwrite, skip or suffix"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollisionPolicy::Overwrite => write!(f, "overwrite"),
+            CollisionPolicy::Skip => write!(f, "skip"),
+            CollisionPolicy::Suffix => write!(f, "suffix"),
+        }
+    }
+}
+
+/**
+ Creates the file and missing parent directories, applying `collision` if the file exists. Returns `None` if the file
+ was skipped
+*/
+pub fn create_file(path: &Path, collision: CollisionPolicy) -> Result<Option<OutputFile>> {
+    if !path.exists() {
+        return OutputFile::create(path).map(Some);
+    }
+    match collision {
+        CollisionPolicy::Overwrite => {
+            warn!("Overwriting {}", path.display());
+            OutputFile::create(path).map(Some)
+        }
+        CollisionPolicy::Skip => {
+            info!("Skipping {}, file exists", path.display());
+            Ok(None)
+        }
+        CollisionPolicy::Suffix => {
+            let candidate = (1..)
+                .map(|i| with_number(path, i))
+                .find(|candidate| !candidate.exists())
+                .unwrap();
+            OutputFile::create(&candidate).map(Some)
+        }
+    }
+}
+
+/**
+ Output file which is written atomically: the content goes to a temporary file in the same directory, which replaces
+ the target only in [`Self::commit`] after fsync. Without commit (e.g. on errors), the temporary file is removed, so
+ there are never half written files
+*/
+pub struct OutputFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl OutputFile {
+    /// Creates missing parent directories and the temporary file. An existing file at `path` is replaced on commit
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = path.with_file_name(format!(".{name}.tmp"));
+        let file = File::create(&tmp_path)?;
+        Ok(OutputFile {
+            path: path.to_path_buf(),
+            tmp_path,
+            writer: Some(BufWriter::new(file)),
+        })
+    }
+
+    /// Final name of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flushes, syncs and renames the temporary file to the final name
+    pub fn commit(mut self) -> Result<PathBuf> {
+        let file = self
+            .writer
+            .take()
+            .unwrap()
+            .into_inner()
+            .map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.tmp_path, &self.path)?;
+        #[cfg(unix)]
+        if let Some(dir) = self.path.parent() {
+            // make the rename itself durable
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(std::mem::take(&mut self.path))
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer.as_mut().expect("not committed yet")
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Seek for OutputFile {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.writer().seek(pos)
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// `dir/name.ext` -> `dir/name_<number>.ext`
+fn with_number(path: &Path, number: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}_{number}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{number}"),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn values() -> FileNameValues {
+        let day = NaiveDate::from_ymd_opt(2025, 7, 31).unwrap();
+        FileNameValues {
+            alias: "Rider 1/2".to_string(),
+            serial: "4102001234".to_string(),
+            device_id: "6c6f7f2c".to_string(),
+            index: 2,
+            start: day.and_hms_opt(20, 8, 0).unwrap(),
+            end: day.and_hms_opt(21, 30, 0).unwrap(),
+            distance_m: 12345.0,
+            ext: "gpx",
+        }
+    }
+
+    #[test]
+    fn render_template() {
+        let template: FileNameTemplate =
+            "{alias}/{start:%Y/%m}/{start}_{device_id}_{{{index}}}.{ext}"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            template.render(&values()).unwrap(),
+            "Rider 1_2/2025/07/2025-07-31_20-08_6c6f7f2c_{2}.gpx"
+        );
+    }
+
+    #[test]
+    fn render_end_distance_serial() {
+        let template: FileNameTemplate = "{serial}_{end:%H%M}_{distance}km".parse().unwrap();
+
+        assert_eq!(
+            template.render(&values()).unwrap(),
+            "4102001234_2130_12.3km"
+        );
+    }
+
+    #[test]
+    fn render_stays_in_output_dir() {
+        let template: FileNameTemplate = "{alias}/{start}.{ext}".parse().unwrap();
+        let mut values = values();
+
+        values.alias = String::new();
+        assert_eq!(
+            template.render(&values).unwrap(),
+            "unnamed/2025-07-31_20-08.gpx"
+        );
+        values.alias = "..".to_string();
+        assert_eq!(template.render(&values).unwrap(), "__/2025-07-31_20-08.gpx");
+
+        let template: FileNameTemplate = "../{start}.{ext}".parse().unwrap();
+        assert!(template.render(&values).is_err());
+        let template: FileNameTemplate = "{start:/%Y}.{ext}".parse().unwrap();
+        assert!(template.render(&values).is_err());
+    }
+
+    #[test]
+    fn legacy_template() {
+        let template = FileNameTemplate::legacy("out/", "_x");
+
+        assert_eq!(
+            template.render(&values()).unwrap(),
+            "out/2025-07-31_20-08_x.gpx"
+        );
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!("{name}.gpx".parse::<FileNameTemplate>().is_err());
+        assert!("{index:%d}.gpx".parse::<FileNameTemplate>().is_err());
+        assert!("{start:%Q}.gpx".parse::<FileNameTemplate>().is_err());
+        assert!("a}.gpx".parse::<FileNameTemplate>().is_err());
+    }
+
+    #[test]
+    fn collision_policies() {
+        let dir = std::env::temp_dir().join(format!("igotu-file-name-{}", std::process::id()));
+        let path = dir.join("sub/a.gpx");
+        let create = |collision| {
+            create_file(&path, collision)
+                .unwrap()
+                .map(|f| f.commit().unwrap())
+        };
+
+        assert_eq!(create(CollisionPolicy::Skip), Some(path.clone()));
+        assert_eq!(create(CollisionPolicy::Skip), None);
+        assert_eq!(
+            create(CollisionPolicy::Suffix),
+            Some(dir.join("sub/a_1.gpx"))
+        );
+        assert_eq!(
+            create(CollisionPolicy::Suffix),
+            Some(dir.join("sub/a_2.gpx"))
+        );
+        assert_eq!(create(CollisionPolicy::Overwrite), Some(path.clone()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn output_file_atomic() {
+        let dir = std::env::temp_dir().join(format!("igotu-output-file-{}", std::process::id()));
+        let path = dir.join("a.gpx");
+        let tmp_path = dir.join(".a.gpx.tmp");
+
+        let mut f = OutputFile::create(&path).unwrap();
+        f.write_all(b"first").unwrap();
+        assert!(tmp_path.exists());
+        assert!(!path.exists());
+        f.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(!tmp_path.exists());
+
+        // not committed, e.g. error while writing: the old content stays
+        let mut f = OutputFile::create(&path).unwrap();
+        f.write_all(b"second").unwrap();
+        drop(f);
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(!tmp_path.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
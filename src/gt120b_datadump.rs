@@ -1,160 +1,810 @@
-use chrono::{DateTime, Duration, Local, NaiveDate, SecondsFormat, Timelike, Utc};
-use log::{info, trace};
-use std::fs::File;
-use std::io::{BufWriter, Result, Write};
+use crate::export_gpx::ButtonWaypoints;
+use crate::file_name::{self, CollisionPolicy, FileNameTemplate, FileNameValues};
+use crate::quality_filter::{DropReason, QualityFilter};
+use crate::timezone::{Timezone, Zone};
+use crate::{
+    export_csv, export_fit, export_geojson, export_gpx, export_kml, export_nmea, export_tcx,
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Timelike, Utc};
+use log::{info, trace, warn};
+use std::fmt;
+use std::io::Result;
+use std::path::PathBuf;
+use std::str::FromStr;
 
+/// One decoded track point
 #[derive(Debug)]
-struct Waypoint {
-    time: DateTime<Utc>,
-    wpflags: u8,
-    sat_used: u8,
-    sat_visib: u8,
-    course: f32,
-    speed: f32,
-    hdop: f32,
-    ele: f32,
-    lat: f32,
-    lon: f32,
+pub struct Waypoint {
+    pub time: DateTime<Utc>,
+    /// 0x01: first point of a track, 0x02: last point of a track, 0x10: button was pressed before this point
+    pub wpflags: u8,
+    pub sat_used: u8,
+    pub sat_visib: u8,
+    /// degrees
+    pub course: f32,
+    /// m/s, as written to gpxtpx:speed
+    pub speed: f32,
+    pub hdop: f32,
+    /// centimeters, as stored in the device
+    pub ele_cm: i32,
+    /// 1e-7 degrees, as stored in the device
+    pub lat_e7: i32,
+    /// 1e-7 degrees, as stored in the device
+    pub lon_e7: i32,
+    /**
+     Bit 0x20 of the record flags, set on some records and not on others. For the GT-120, igotu2gpx reads it as
+     "position not valid", for the GT-120B its meaning is not confirmed. The record is decoded the same way
+    */
+    pub flag_20: bool,
+}
+
+impl Waypoint {
+    /// degrees
+    pub fn lat(&self) -> f64 {
+        self.lat_e7 as f64 / 1e7
+    }
+
+    /// degrees
+    pub fn lon(&self) -> f64 {
+        self.lon_e7 as f64 / 1e7
+    }
+
+    /// meters
+    pub fn ele(&self) -> f64 {
+        self.ele_cm as f64 / 100.0
+    }
+
+    /// Great circle distance in meters (haversine formula)
+    pub fn distance_to(&self, other: &Waypoint) -> f64 {
+        const EARTH_RADIUS_M: f64 = 6_371_000.0;
+        let (lat1, lat2) = (self.lat().to_radians(), other.lat().to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon() - self.lon()).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
 }
 
 #[derive(Debug, PartialEq)]
-enum ButtonEnum {
+pub enum ButtonEnum {
     On,
     Off,
     Trigger,
 }
 
+/// One decoded record of the device memory
 #[derive(Debug)]
-enum DatablockEnum {
+pub enum DatablockEnum {
     Datablock(Waypoint),
-    Button(DateTime<Utc>, ButtonEnum),
+    /// Flags 0x41 and 0x43. The bool is bit 0x20 of the flags, see [`Waypoint::flag_20`]
+    Button(DateTime<Utc>, ButtonEnum, bool),
     ButtonWithoutTime(ButtonEnum),
+    /// Flags 0x42: switched off, with the last position. Exported only with [`Gt120bDataDump::set_switch_off_points`]
+    SwitchOff(Waypoint),
+    /// Flags 0x50: record with time, but without position. Not exported. The bool is bit 0x20 of the flags
+    WithoutPosition(DateTime<Utc>, bool),
+    /// Record with unknown flags or invalid time, kept as it is for diagnostics
+    Unknown {
+        offset: u32,
+        raw: Vec<u8>,
+    },
     NoBlock,
 }
 
 impl DatablockEnum {
-    pub fn dump<T: std::io::Write>(&self, f: &mut BufWriter<T>) -> Result<()> {
-        if let DatablockEnum::Datablock(wpt) = self {
-            writeln!(
-                f,
-                "      <trkpt lat=\"{}\" lon=\"{}\">
-        <ele>{}</ele>
-        <time>{}</time>{}
-        <sat>{}</sat>
-        <hdop>{}</hdop>
-        <extensions>
-          <gpxtpx:TrackPointExtension>
-            <gpxtpx:speed>{}</gpxtpx:speed>
-            <gpxtpx:course>{}</gpxtpx:course>
-          </gpxtpx:TrackPointExtension>
-          <mat:TrackPointExtension>
-            <mat:sat_view>{}</mat:sat_view>
-          </mat:TrackPointExtension>
-        </extensions>
-      </trkpt>",
-                &wpt.lat,
-                &wpt.lon,
-                &wpt.ele,
-                &wpt.time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
-                if wpt.wpflags != 0 {
-                    format!("\n        <type>WpFlag:{}</type>", &wpt.wpflags)
-                } else {
-                    "".to_string()
-                },
-                &wpt.sat_used,
-                &wpt.hdop,
-                &wpt.speed,
-                &wpt.course,
-                &wpt.sat_visib,
-            )?;
-        }
-        Ok(())
-    }
-
     pub fn is_new_file(&self) -> bool {
         match self {
             DatablockEnum::Datablock(wp) => (wp.wpflags & 0x01) != 0,
-            DatablockEnum::Button(_, typ) => matches!(*typ, ButtonEnum::On),
+            DatablockEnum::Button(_, typ, _) => matches!(*typ, ButtonEnum::On),
             _ => false,
         }
     }
     pub fn time(&self) -> DateTime<Utc> {
         match self {
             DatablockEnum::Datablock(wp) => wp.time,
-            DatablockEnum::Button(time, _) => *time,
+            DatablockEnum::Button(time, _, _) => *time,
+            DatablockEnum::SwitchOff(wp) => wp.time,
+            DatablockEnum::WithoutPosition(time, _) => *time,
             DatablockEnum::ButtonWithoutTime(_) => panic!("No time available"),
+            DatablockEnum::Unknown { .. } => panic!("No time available"),
             DatablockEnum::NoBlock => panic!("No time available"),
         }
     }
     pub fn time_opt(&self) -> Option<DateTime<Utc>> {
         match self {
             DatablockEnum::Datablock(wp) => Some(wp.time),
-            DatablockEnum::Button(time, _) => Some(*time),
+            DatablockEnum::Button(time, _, _) => Some(*time),
+            DatablockEnum::SwitchOff(wp) => Some(wp.time),
+            DatablockEnum::WithoutPosition(time, _) => Some(*time),
             DatablockEnum::ButtonWithoutTime(_) => None,
+            DatablockEnum::Unknown { .. } => None,
             DatablockEnum::NoBlock => None,
         }
     }
 }
 
+/// Track points belonging to one output file
+#[derive(Debug)]
+pub struct Track<'a> {
+    /// Continuous parts of the track. Currently always one
+    pub segments: Vec<Vec<&'a Waypoint>>,
+    /// Times of button presses ([`ButtonEnum::Trigger`]) during the track
+    pub trigger_times: Vec<DateTime<Utc>>,
+    /// Time of the switch-on record (flags 0x41) before the first point, if any
+    pub switch_on: Option<DateTime<Utc>>,
+    /// Time of the switch-off record (flags 0x42) after the last point, if any
+    pub switch_off: Option<DateTime<Utc>>,
+}
+
+impl<'a> Track<'a> {
+    pub fn points(&self) -> impl Iterator<Item = &'a Waypoint> + '_ {
+        self.segments.iter().flatten().copied()
+    }
+
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.points().next().expect("tracks are never empty").time
+    }
+
+    pub fn end_time(&self) -> DateTime<Utc> {
+        self.points().last().expect("tracks are never empty").time
+    }
+
+    /// Sum of the distances between the points, in meters
+    pub fn distance_m(&self) -> f64 {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.windows(2))
+            .map(|w| w[0].distance_to(w[1]))
+            .sum()
+    }
+
+    /// Button presses, each with the track point nearest in time
+    pub fn triggers(&self) -> Vec<(DateTime<Utc>, &'a Waypoint)> {
+        self.trigger_times
+            .iter()
+            .filter_map(|&time| {
+                self.points()
+                    .min_by_key(|wpt| (wpt.time - time).abs())
+                    .map(|wpt| (time, wpt))
+            })
+            .collect()
+    }
+}
+
+/// File format for [`Gt120bDataDump::write_out`]
+#[derive(
+    strum_macros::Display, strum_macros::EnumString, Debug, Clone, Copy, PartialEq, Default,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Gpx,
+    /// Google Earth
+    Kml,
+    /// Zipped KML
+    Kmz,
+    /// For web mapping
+    GeoJson,
+    /// All records in one file, including button presses
+    Csv,
+    /// Garmin Training Center, one activity per day with a lap per track
+    Tcx,
+    /// Garmin FIT activity, one per day with a lap per track
+    Fit,
+    /// NMEA 0183 sentences, for legacy tools
+    Nmea,
+}
+
+impl ExportFormat {
+    /// How files are split if not configured otherwise
+    pub fn default_split(&self) -> SplitPolicy {
+        match self {
+            ExportFormat::Tcx | ExportFormat::Fit => SplitPolicy::Day,
+            ExportFormat::Csv => SplitPolicy::Single,
+            _ => SplitPolicy::Track,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "gpx",
+            ExportFormat::Kml => "kml",
+            ExportFormat::Kmz => "kmz",
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Tcx => "tcx",
+            ExportFormat::Fit => "fit",
+            ExportFormat::Nmea => "nmea",
+        }
+    }
+}
+
+/// How the tracks are distributed to files
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SplitPolicy {
+    /// One file per track. Tracks are also split when the local day changes, like the original software does
+    #[default]
+    Track,
+    /// All tracks in one file
+    Single,
+    /// One file per local day
+    Day,
+    /// One file per N hours, counted from local midnight. N divides 24, so all windows have the same length
+    Hours(u32),
+}
+
+impl FromStr for SplitPolicy {
+    type Err = String;
+
+    /// `track`, `single`, `day` or a divisor of 24 hours like `6h`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "track" => Ok(SplitPolicy::Track),
+            "single" => Ok(SplitPolicy::Single),
+            "day" => Ok(SplitPolicy::Day),
+            _ => match s.strip_suffix('h').and_then(|h| h.parse::<u32>().ok()) {
+                Some(hours) if hours > 0 && 24 % hours == 0 => Ok(SplitPolicy::Hours(hours)),
+                Some(hours) => Err(format!(
+                    "invalid split policy <{s}>, {hours} hours don't divide a day. Use 1, 2, 3, 4, 6, 8, 12 or 24"
+                )),
+                None => Err(format!(
+                    "invalid split policy <{s}>, expected track, single, day or hours like 6h"
+                )),
+            },
+        }
+    }
+}
+
+impl SplitPolicy {
+    /// Whether tracks are also split at the local day change, see [`Gt120bDataDump::tracks`]
+    pub fn change_every_day(&self) -> bool {
+        matches!(self, SplitPolicy::Track | SplitPolicy::Day)
+    }
+}
+
+impl fmt::Display for SplitPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitPolicy::Track => write!(f, "track"),
+            SplitPolicy::Single => write!(f, "single"),
+            SplitPolicy::Day => write!(f, "day"),
+            SplitPolicy::Hours(hours) => write!(f, "{hours}h"),
+        }
+    }
+}
+
+/// Size of one record in a chunk
+pub const RECORD_SIZE: usize = 30;
+/// Size of a chunk: header and 4 records
+pub const CHUNK_SIZE: usize = 8 + 4 * RECORD_SIZE;
+/// First 4 bytes of the header of every chunk with data seen so far
+pub const CHUNK_MAGIC: [u8; 4] = [0x0c, 0x00, 0x41, 0x02];
+
+/**
+ Header of a 128-byte chunk, the 8 bytes before its 4 records, e.g. `0c 00 41 02 98 44 03 cc`.
+
+ The first 4 bytes were always [`CHUNK_MAGIC`]. The other 4 bytes vary between chunks. They are not a CRC-32 or
+ CRC-16 of the records or their sum, their meaning is unknown yet. A chunk which was never written is all 0xff
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkHeader {
+    pub magic: [u8; 4],
+    pub tag: [u8; 4],
+}
+
+/// Inconsistency between a chunk header and its records, see [`ChunkHeader::validate`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkError {
+    /// The header is neither erased nor starts with [`CHUNK_MAGIC`]
+    UnknownMagic([u8; 4]),
+    /// Erased header, but records: possibly a stale sector or an interrupted write
+    ErasedWithRecords,
+    /// Header written, but no records
+    HeaderWithoutRecords,
+    /// A record follows an empty one. Records are written in order, so this is unexpected
+    RecordAfterEmpty(usize),
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkError::UnknownMagic(magic) => write!(f, "unknown header {magic:02x?}"),
+            ChunkError::ErasedWithRecords => write!(f, "erased header, but records"),
+            ChunkError::HeaderWithoutRecords => write!(f, "header, but no records"),
+            ChunkError::RecordAfterEmpty(n) => write!(f, "record {n} after an empty record"),
+        }
+    }
+}
+
+impl ChunkHeader {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        ChunkHeader {
+            magic: bytes[0..4].try_into().unwrap(),
+            tag: bytes[4..8].try_into().unwrap(),
+        }
+    }
+
+    pub fn is_erased(&self) -> bool {
+        self.magic == [0xff; 4] && self.tag == [0xff; 4]
+    }
+
+    /**
+     Checks the header against the 4 records following it: a chunk is either erased completely, or has a header
+     with the known magic and its records from the beginning (the last chunk possibly incomplete)
+    */
+    pub fn validate(&self, records: &[u8]) -> std::result::Result<(), ChunkError> {
+        let used: Vec<bool> = records
+            .chunks(RECORD_SIZE)
+            .map(|record| record[0] != 0xff)
+            .collect();
+        if self.is_erased() {
+            return match used.contains(&true) {
+                true => Err(ChunkError::ErasedWithRecords),
+                false => Ok(()),
+            };
+        }
+        if self.magic != CHUNK_MAGIC {
+            return Err(ChunkError::UnknownMagic(self.magic));
+        }
+        if !used[0] {
+            return match used.iter().position(|&u| u) {
+                Some(n) => Err(ChunkError::RecordAfterEmpty(n)),
+                None => Err(ChunkError::HeaderWithoutRecords),
+            };
+        }
+        match used.windows(2).position(|w| !w[0] && w[1]) {
+            Some(n) => Err(ChunkError::RecordAfterEmpty(n + 1)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ChunkHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", hex::encode(self.magic), hex::encode(self.tag))
+    }
+}
+
+/// One file of an export, see [`Gt120bDataDump::write_out`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedFile {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    /// Number of track points written to the file
+    pub points: usize,
+    /// `false` if the file existed already and was kept, see [`CollisionPolicy::Skip`]
+    pub written: bool,
+}
+
 pub struct Gt120bDataDump {
     waypoints: Vec<DatablockEnum>,
-    conf_prefix: String,
-    conf_suffix: String,
+    /// flash offset of each record in `waypoints`
+    offsets: Vec<u32>,
+    conf_name_template: FileNameTemplate,
+    conf_output_dir: PathBuf,
+    conf_collision: CollisionPolicy,
+    /// device values for the file names, the other fields are set per file
+    conf_name_values: FileNameValues,
+    conf_skip_until: Option<DateTime<Utc>>,
+    conf_day_change_grace: Duration,
+    conf_button_waypoints: Option<ButtonWaypoints>,
+    conf_orig_sw_gpx: bool,
+    conf_switch_off_points: bool,
+    conf_quality_filter: QualityFilter,
+    conf_keep_raw: bool,
+    conf_timezone: Option<Timezone>,
+    raw_blocks: Vec<(u32, Vec<u8>)>,
+    /// chunks with inconsistent headers, by flash offset
+    chunk_errors: Vec<(u32, ChunkError)>,
+    /// flash offsets of records with a time before the record preceding them
+    out_of_order: Vec<u32>,
 }
 
 impl Gt120bDataDump {
     pub fn new(prefix: String, suffix: String) -> Self {
         Gt120bDataDump {
             waypoints: Vec::new(),
-            conf_prefix: prefix,
-            conf_suffix: suffix,
-        }
-    }
-
-    pub fn process_datablock(&mut self, data: Vec<u8>) {
-        let structsize = 8 + 4 * 30;
-        assert_eq!(0, data.len() % structsize);
-        self.parse_data(data);
-    }
-
-    pub fn write_out(&mut self, conf_change_every_day: bool, meta_desc: &String) -> Result<usize> {
-        fn start_file(name: &str, meta_desc: &String) -> Result<Option<BufWriter<File>>> {
-            info!("Writing gpx file {name}");
-            let f = File::create(name)?;
-            let mut fbuf = BufWriter::new(f);
-            assert!(fbuf.capacity() > 0);
-            writeln!(&mut fbuf,"﻿<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"no\"?>
-<!-- generated using test of rust implementation -->
-<gpx version=\"1.1\" creator=\"igotU_GPS_WIN\" xmlns:gpxx=\"http://www.garmin.com/xmlschemas/GpxExtensions/v3\" xmlns:gpxwpx=\"http://www.garmin.com/xmlschemas/WaypointExtension/v1\" xmlns:gpxtpx=\"http://www.garmin.com/xmlschemas/TrackPointExtension/v2\" xmlns:mat=\"http://www.mobileaction.com/xmlschemas/TrackPointExtension/v2\" xmlns=\"http://www.topografix.com/GPX/1/1\">
-  <metadata>
-    <desc>{meta_desc}</desc>
-  </metadata>
-  <trk>
-    <trkseg>")?;
-            fbuf.flush()?;
-            Ok(Some(fbuf))
-        }
-        #[allow(clippy::toplevel_ref_arg)]
-        fn end_file(ref mut f_ref: Option<BufWriter<File>>) -> Result<()> {
-            if let Some(f) = f_ref {
-                writeln!(
-                    f,
-                    "    </trkseg>
-  </trk>
-</gpx>"
-                )?;
-                f.flush()?;
+            offsets: Vec::new(),
+            conf_name_template: FileNameTemplate::legacy(&prefix, &suffix),
+            conf_output_dir: PathBuf::new(),
+            conf_collision: CollisionPolicy::default(),
+            conf_name_values: FileNameValues::default(),
+            conf_skip_until: None,
+            conf_day_change_grace: Duration::hours(4),
+            conf_button_waypoints: None,
+            conf_orig_sw_gpx: false,
+            conf_switch_off_points: false,
+            conf_quality_filter: QualityFilter::default(),
+            conf_keep_raw: false,
+            conf_timezone: None,
+            raw_blocks: Vec::new(),
+            chunk_errors: Vec::new(),
+            out_of_order: Vec::new(),
+        }
+    }
+
+    /// Keep a copy of all memory blocks processed, e.g. for a raw dump
+    pub fn set_keep_raw(&mut self, keep_raw: bool) {
+        self.conf_keep_raw = keep_raw;
+    }
+
+    /// Memory blocks processed so far with their offset, in the order of processing. Only if enabled by `set_keep_raw`
+    pub fn raw_blocks(&self) -> &[(u32, Vec<u8>)] {
+        &self.raw_blocks
+    }
+
+    /// Chunks with a header not matching their records, by flash offset, see [`ChunkHeader::validate`]
+    pub fn chunk_errors(&self) -> &[(u32, ChunkError)] {
+        &self.chunk_errors
+    }
+
+    /// Flash offsets of records with a time before the record preceding them. They are kept and exported
+    pub fn out_of_order(&self) -> &[u32] {
+        &self.out_of_order
+    }
+
+    /// Records which couldn't be decoded, with their flash offset
+    pub fn unknown_records(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.waypoints.iter().filter_map(|wp| match wp {
+            DatablockEnum::Unknown { offset, raw } => Some((*offset, raw.as_slice())),
+            _ => None,
+        })
+    }
+
+    /// Skip all records up to and including this time, because they were downloaded before
+    pub fn set_skip_until(&mut self, time: Option<DateTime<Utc>>) {
+        self.conf_skip_until = time;
+    }
+
+    /**
+     A track is not split at the local day change if it started less than this before (counted from the full hour of
+     the start). Default: 4 hours
+    */
+    pub fn set_day_change_grace(&mut self, grace: Duration) {
+        self.conf_day_change_grace = grace;
+    }
+
+    /// Write button presses as `<wpt>` into gpx files
+    pub fn set_button_waypoints(&mut self, button_waypoints: Option<ButtonWaypoints>) {
+        self.conf_button_waypoints = button_waypoints;
+    }
+
+    /// Write gpx files byte-compatible to the original software instead of the standard XML writer
+    pub fn set_orig_sw_gpx(&mut self, orig_sw_gpx: bool) {
+        self.conf_orig_sw_gpx = orig_sw_gpx;
+    }
+
+    /**
+     Append the position of the switch-off record (flags 0x42) to the track as its last point. The original software
+     ignores it, but it is a valid fix, taken where the logger was switched off
+    */
+    pub fn set_switch_off_points(&mut self, switch_off_points: bool) {
+        self.conf_switch_off_points = switch_off_points;
+    }
+
+    /**
+     Drop implausible track points before the export, see [`QualityFilter`]. A point failing the speed or jump check,
+     but consistent with the point dropped just before it, is kept and becomes the new reference, so a real gap only
+     costs one point
+    */
+    pub fn set_quality_filter(&mut self, quality_filter: QualityFilter) {
+        self.conf_quality_filter = quality_filter;
+    }
+
+    /**
+     Timezone for file names and day splitting. Default (`None`): file names in UTC and day splitting in the local
+     timezone, like the original software
+    */
+    pub fn set_timezone(&mut self, timezone: Option<Timezone>) {
+        self.conf_timezone = timezone;
+    }
+
+    /// Timezone for day splitting, `Auto` resolved from the first waypoint
+    fn zone(&self) -> Zone {
+        self.conf_timezone.map_or(Zone::Local, |timezone| {
+            timezone.resolve(self.first_position())
+        })
+    }
+
+    fn first_position(&self) -> Option<(f64, f64)> {
+        self.waypoints.iter().find_map(|wp| match wp {
+            DatablockEnum::Datablock(wpt) => Some((wpt.lat(), wpt.lon())),
+            _ => None,
+        })
+    }
+
+    /// Time for file names and titles: in UTC without configured timezone, otherwise in the resolved timezone
+    fn name_time(&self, zone: Zone, time: &DateTime<Utc>) -> NaiveDateTime {
+        match self.conf_timezone {
+            None => time.naive_utc(),
+            Some(_) => zone.naive_local(time),
+        }
+    }
+
+    fn file_time(&self, zone: Zone, time: &DateTime<Utc>) -> String {
+        self.name_time(zone, time)
+            .format(file_name::DEFAULT_TIME_FORMAT)
+            .to_string()
+    }
+
+    /// File names relative to `output_dir`. Default: [`FileNameTemplate::legacy`] with prefix and suffix of [`Self::new`]
+    pub fn set_file_names(
+        &mut self,
+        output_dir: PathBuf,
+        template: FileNameTemplate,
+        collision: CollisionPolicy,
+    ) {
+        self.conf_output_dir = output_dir;
+        self.conf_name_template = template;
+        self.conf_collision = collision;
+    }
+
+    /// Device values for the placeholders `{alias}`, `{serial}` and `{device_id}` of the file names
+    pub fn set_device(&mut self, alias: &str, serial: &str, device_id: &str) {
+        self.conf_name_values.alias = alias.to_string();
+        self.conf_name_values.serial = serial.to_string();
+        self.conf_name_values.device_id = device_id.to_string();
+    }
+
+    /// Output file name from the template
+    fn output_path(
+        &self,
+        zone: Zone,
+        index: usize,
+        ext: &'static str,
+        time_range: (DateTime<Utc>, DateTime<Utc>),
+        distance_m: f64,
+    ) -> Result<PathBuf> {
+        let values = FileNameValues {
+            index,
+            start: self.name_time(zone, &time_range.0),
+            end: self.name_time(zone, &time_range.1),
+            distance_m,
+            ext,
+            ..self.conf_name_values.clone()
+        };
+        Ok(self
+            .conf_output_dir
+            .join(self.conf_name_template.render(&values)?))
+    }
+
+    /// Number of track points, after [`Self::prepare_data`] the number of points exported
+    pub fn num_points(&self) -> usize {
+        self.track_points().count()
+    }
+
+    /// Time of the latest exported waypoint
+    pub fn last_time(&self) -> Option<DateTime<Utc>> {
+        self.track_points().fold(None, |_, wp| Some(wp.time()))
+    }
+
+    /**
+     The records exported as track points: all fixes, and with [`Self::set_switch_off_points`] the switch-off records.
+     A switch-off record often repeats the fix before it, then it is no extra point
+    */
+    fn track_points(&self) -> impl Iterator<Item = &DatablockEnum> {
+        let mut last_time = None;
+        self.waypoints.iter().filter(move |wp| {
+            let point = match wp {
+                DatablockEnum::Datablock(_) => true,
+                DatablockEnum::SwitchOff(wpt) => {
+                    self.conf_switch_off_points && last_time != Some(wpt.time)
+                }
+                _ => false,
+            };
+            if point {
+                last_time = Some(wp.time());
+            }
+            point
+        })
+    }
+
+    /// All records parsed so far, in device order
+    pub fn waypoints(&self) -> &[DatablockEnum] {
+        &self.waypoints
+    }
+
+    /// All records parsed so far with their flash offset, in device order
+    pub fn records(&self) -> impl Iterator<Item = (u32, &DatablockEnum)> {
+        self.offsets.iter().copied().zip(self.waypoints.iter())
+    }
+
+    /// Parses a memory block read at flash offset `offset`
+    pub fn process_datablock(&mut self, offset: u32, data: Vec<u8>) {
+        assert_eq!(0, data.len() % CHUNK_SIZE);
+        if self.conf_keep_raw {
+            self.raw_blocks.push((offset, data.clone()));
+        }
+        self.parse_data(offset, data);
+    }
+
+    /**
+     Writes the tracks in the given format, distributed to files according to `split`. Csv is always a single file.
+     Every file is written atomically, see [`file_name::OutputFile`]. Returns the files, including the ones skipped because they
+     exist already
+    */
+    pub fn write_out(
+        &mut self,
+        split: SplitPolicy,
+        format: ExportFormat,
+        meta_desc: &str,
+    ) -> Result<Vec<ExportedFile>> {
+        self.prepare_data();
+        if self.conf_quality_filter.is_active() {
+            self.filter_points();
+        }
+        let zone = self.zone();
+        match (self.conf_timezone, self.first_position()) {
+            (Some(Timezone::Auto), None) => {
+                warn!("Timezone: no position for --timezone auto, using {zone}")
+            }
+            (Some(Timezone::Auto), Some((lat, lon))) => warn!(
+                "Timezone: {zone}, nearest reference location to {lat:.4},{lon:.4}. This may be wrong near timezone borders, set the name with --timezone then"
+            ),
+            (Some(_), _) => info!("Timezone: {zone}"),
+            (None, _) => {}
+        }
+
+        if format == ExportFormat::Csv {
+            if split != SplitPolicy::Single {
+                warn!("--split {split} is ignored for csv, all records are written to one file");
+            }
+            let mut times = self.waypoints.iter().filter_map(|wp| wp.time_opt());
+            let Some(first_time) = times.next() else {
+                info!("Exported 0 files");
+                return Ok(Vec::new());
+            };
+            let last_time = times.next_back().unwrap_or(first_time);
+            let distance_m = self
+                .tracks_in(zone, false)
+                .iter()
+                .map(|track| track.distance_m())
+                .sum();
+            let path = self.output_path(
+                zone,
+                1,
+                format.extension(),
+                (first_time, last_time),
+                distance_m,
+            )?;
+            let mut exported = ExportedFile {
+                path,
+                format,
+                points: self.num_points(),
+                written: false,
+            };
+            if let Some(mut f) = file_name::create_file(&exported.path, self.conf_collision)? {
+                info!("Writing {format} file {}", f.path().display());
+                export_csv::write_csv(&mut f, self.records(), self.conf_switch_off_points)?;
+                exported.path = f.commit()?;
+                exported.written = true;
+            }
+            info!("Exported {} files", usize::from(exported.written));
+            return Ok(vec![exported]);
+        }
+
+        let tracks = self.tracks_in(zone, split.change_every_day());
+        let files: Vec<&[Track]> = match split {
+            SplitPolicy::Track => tracks.chunks(1).collect(),
+            SplitPolicy::Single if tracks.is_empty() => Vec::new(),
+            SplitPolicy::Single => vec![&tracks[..]],
+            SplitPolicy::Day => tracks
+                .chunk_by(|a, b| {
+                    zone.naive_local(&a.start_time()).date()
+                        == zone.naive_local(&b.start_time()).date()
+                })
+                .collect(),
+            SplitPolicy::Hours(hours) => {
+                let window = |track: &Track| {
+                    let local = zone.naive_local(&track.start_time());
+                    (local.date(), local.hour() / hours)
+                };
+                tracks.chunk_by(|a, b| window(a) == window(b)).collect()
+            }
+        };
+        let mut exported = Vec::new();
+        for (index, file_tracks) in files.iter().enumerate() {
+            let title = self.file_time(zone, &file_tracks[0].start_time());
+            let path = self.output_path(
+                zone,
+                index + 1,
+                format.extension(),
+                (
+                    file_tracks[0].start_time(),
+                    file_tracks[file_tracks.len() - 1].end_time(),
+                ),
+                file_tracks.iter().map(|track| track.distance_m()).sum(),
+            )?;
+            let points = file_tracks.iter().map(|track| track.points().count()).sum();
+            let Some(mut f) = file_name::create_file(&path, self.conf_collision)? else {
+                exported.push(ExportedFile {
+                    path,
+                    format,
+                    points,
+                    written: false,
+                });
+                continue;
+            };
+            info!("Writing {format} file {}", f.path().display());
+            let in_tracks = self.conf_button_waypoints.as_ref().filter(|b| b.in_tracks);
+            match format {
+                ExportFormat::Gpx if self.conf_orig_sw_gpx => {
+                    export_gpx::write_tracks(&mut f, meta_desc, file_tracks, in_tracks)?
+                }
+                ExportFormat::Gpx => {
+                    export_gpx::write_gpx(&mut f, meta_desc, file_tracks, in_tracks)?
+                }
+                ExportFormat::Kml => export_kml::write_kml(&mut f, &title, meta_desc, file_tracks)?,
+                ExportFormat::Kmz => export_kml::write_kmz(&mut f, &title, meta_desc, file_tracks)?,
+                ExportFormat::GeoJson => {
+                    export_geojson::write_geojson(&mut f, &title, meta_desc, file_tracks)?
+                }
+                ExportFormat::Tcx => export_tcx::write_tcx(&mut f, file_tracks)?,
+                ExportFormat::Fit => export_fit::write_fit(&mut f, file_tracks)?,
+                ExportFormat::Nmea => export_nmea::write_nmea(&mut f, file_tracks)?,
+                ExportFormat::Csv => unreachable!("written as single file above"),
+            }
+            let name = f.commit()?;
+            exported.push(ExportedFile {
+                path: name.clone(),
+                format,
+                points,
+                written: true,
+            });
+
+            if let Some(button_waypoints) = &self.conf_button_waypoints
+                && button_waypoints.poi_file
+                && format == ExportFormat::Gpx
+                && file_tracks
+                    .iter()
+                    .any(|track| !track.trigger_times.is_empty())
+            {
+                let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+                let poi_name = name.with_file_name(format!("{stem}_poi.gpx"));
+                let Some(mut f) = file_name::create_file(&poi_name, self.conf_collision)? else {
+                    continue;
+                };
+                info!("Writing poi file {}", f.path().display());
+                if self.conf_orig_sw_gpx {
+                    export_gpx::write_poi(&mut f, meta_desc, file_tracks, &button_waypoints.sym)?;
+                } else {
+                    export_gpx::write_gpx_poi(
+                        &mut f,
+                        meta_desc,
+                        file_tracks,
+                        &button_waypoints.sym,
+                    )?;
+                }
+                exported.push(ExportedFile {
+                    path: f.commit()?,
+                    format,
+                    points: 0,
+                    written: true,
+                });
             }
-            Ok(())
         }
+        let num_files = exported.iter().filter(|file| file.written).count();
+        info!("Exported {num_files} files");
+        Ok(exported)
+    }
+
+    /**
+     Groups the waypoints into tracks. A new track is started at every switch-on and optionally when the local day
+     changes (in the configured timezone). Call [`Self::prepare_data`] first
+    */
+    pub fn tracks(&self, conf_change_every_day: bool) -> Vec<Track<'_>> {
+        self.tracks_in(self.zone(), conf_change_every_day)
+    }
 
+    fn tracks_in(&self, zone: Zone, conf_change_every_day: bool) -> Vec<Track<'_>> {
         fn set_daychange(
+            zone: Zone,
             time: &DateTime<Utc>,
+            grace: Duration,
             lastday: &mut NaiveDate,
             skip_day_change_before_before: &mut DateTime<Utc>,
         ) {
-            let localdatetime: DateTime<Local> = DateTime::from(*time);
-            let day = localdatetime.date_naive();
+            let day = zone.naive_local(time).date();
             *lastday = day;
 
             let until: DateTime<Utc> = time
@@ -162,64 +812,89 @@ impl Gt120bDataDump {
                 .and_hms_opt(time.hour(), 0, 0)
                 .unwrap()
                 .and_utc()
-                + Duration::hours(4);
+                + grace;
             *skip_day_change_before_before = until;
         }
         fn need_daychange(
+            zone: Zone,
             time: &DateTime<Utc>,
             lastday: &NaiveDate,
             skip_day_change_before_before: &DateTime<Utc>,
         ) -> bool {
-            let localdatetime: DateTime<Local> = DateTime::from(*time);
-            let day = localdatetime.date_naive();
+            let day = zone.naive_local(time).date();
             if time <= skip_day_change_before_before {
                 return false;
             }
             day != *lastday
         }
 
-        self.prepare_data();
-
         let mut lastday = NaiveDate::MIN;
         let mut skip_day_change_before = DateTime::<Utc>::MIN_UTC;
 
-        let mut f_ref: Option<BufWriter<File>> = None;
-        let mut filenum = 0;
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut current: Option<Track> = None;
+        // button presses seen while no track was open. They belong to the next track
+        let mut pending_triggers = Vec::new();
+        let mut pending_switch_on = None;
         for wp in &self.waypoints {
-            if let DatablockEnum::Datablock(wpt) = wp {
-                if f_ref.is_some() && wp.is_new_file() {
-                    end_file(f_ref)?;
-                    f_ref = None;
-                }
-                if f_ref.is_some()
-                    && conf_change_every_day
-                    && need_daychange(&wpt.time, &lastday, &skip_day_change_before)
+            match wp {
+                DatablockEnum::SwitchOff(wpt)
+                    if !self.conf_switch_off_points
+                        || current
+                            .as_ref()
+                            .and_then(|track| track.points().last())
+                            .is_some_and(|last| last.time == wpt.time) =>
                 {
-                    end_file(f_ref)?;
-                    f_ref = None;
+                    if let Some(track) = &mut current {
+                        track.switch_off = Some(wpt.time);
+                    }
                 }
-                if f_ref.is_none() {
-                    filenum += 1;
-                    f_ref = start_file(
-                        &format!(
-                            "{}{}{}.gpx",
-                            self.conf_prefix,
-                            wpt.time.format("%Y-%m-%d_%H-%M"),
-                            self.conf_suffix
-                        )
-                        .to_string(),
-                        meta_desc,
-                    )?;
-                    set_daychange(&wpt.time, &mut lastday, &mut skip_day_change_before);
+                DatablockEnum::Datablock(wpt) | DatablockEnum::SwitchOff(wpt) => {
+                    let switch_off = matches!(wp, DatablockEnum::SwitchOff(_));
+                    // the switch-off point always ends its track, even after a day change
+                    if current.is_some()
+                        && !switch_off
+                        && (wp.is_new_file()
+                            || conf_change_every_day
+                                && need_daychange(
+                                    zone,
+                                    &wpt.time,
+                                    &lastday,
+                                    &skip_day_change_before,
+                                ))
+                    {
+                        tracks.extend(current.take());
+                    }
+                    let track = current.get_or_insert_with(|| {
+                        set_daychange(
+                            zone,
+                            &wpt.time,
+                            self.conf_day_change_grace,
+                            &mut lastday,
+                            &mut skip_day_change_before,
+                        );
+                        Track {
+                            segments: vec![Vec::new()],
+                            trigger_times: std::mem::take(&mut pending_triggers),
+                            switch_on: pending_switch_on.take(),
+                            switch_off: None,
+                        }
+                    });
+                    track.segments.last_mut().unwrap().push(wpt);
+                    if switch_off {
+                        track.switch_off = Some(wpt.time);
+                    }
                 }
-                wp.dump(f_ref.as_mut().expect("at this stage, file is always open"))?;
+                DatablockEnum::Button(time, ButtonEnum::Trigger, _) => match &mut current {
+                    Some(track) => track.trigger_times.push(*time),
+                    None => pending_triggers.push(*time),
+                },
+                DatablockEnum::Button(time, ButtonEnum::On, _) => pending_switch_on = Some(*time),
+                _ => {}
             }
         }
-        if f_ref.is_some() {
-            end_file(f_ref)?;
-        }
-        info!("Exported {filenum} files");
-        Ok(filenum)
+        tracks.extend(current);
+        tracks
     }
 
     pub fn prepare_data(&mut self) {
@@ -239,28 +914,147 @@ impl Gt120bDataDump {
             }
         }
 
-        // not sorting, but check if sorted. Problems are the ButtonWithoutTime
-        //self.waypoints.sort_by_key(|a| a.time());
-        assert!(
-            self.waypoints
-                .iter()
-                .filter_map(|w| w.time_opt())
-                .collect::<Vec<_>>()
-                .windows(2)
-                .all(|w| w[0] <= w[1])
-        );
+        // not sorting, the device order decides. Problems are the ButtonWithoutTime
         dump_time_range(&self.waypoints);
+        if !self.out_of_order.is_empty() {
+            warn!(
+                "  {} records with a time before the record preceding them, see warnings above",
+                self.out_of_order.len()
+            );
+        }
+        if !self.chunk_errors.is_empty() {
+            warn!(
+                "  {} chunks with inconsistent headers, see warnings above",
+                self.chunk_errors.len()
+            );
+        }
+        let unknown: Vec<(u32, &[u8])> = self.unknown_records().collect();
+        if !unknown.is_empty() {
+            warn!(
+                "  {} records with unknown flags or invalid time, ignored:",
+                unknown.len()
+            );
+            for (offset, raw) in &unknown {
+                warn!("    0x{offset:06x}: {raw:02x?}");
+            }
+        }
+        let without_position = self
+            .waypoints
+            .iter()
+            .filter(|wp| matches!(wp, DatablockEnum::WithoutPosition(..)))
+            .count();
+        if without_position > 0 {
+            info!("  {without_position} records without position");
+        }
 
         self.transfer_flags_forward();
         self.transfer_flags_backward();
+
+        if let Some(skip_until) = self.conf_skip_until {
+            // the first record after skip_until. Records without time in between are dropped too
+            let skip_num = self
+                .waypoints
+                .iter()
+                .position(|wp| wp.time_opt().is_some_and(|t| t > skip_until))
+                .unwrap_or(self.waypoints.len());
+            info!("  Skipping {skip_num} records already downloaded before");
+            self.waypoints.drain(..skip_num);
+            self.offsets.drain(..skip_num);
+        }
+    }
+
+    /**
+     Removes the track points failing the [`QualityFilter`]. Needs the flags from [`Self::prepare_data`]: the start and
+     button flags of a dropped point move to the next point kept, the end flag to the previous one, so the tracks are
+     still split the same way. Returns the number of points dropped per reason.
+
+     Speed and jump are checked against the previous point kept. After a real gap (tunnel, smart tracking pause) this
+     point is far away, so a point which is too far from it but consistent with the point dropped just before is kept
+     and becomes the new reference. Otherwise everything up to the next switch-on would be dropped
+    */
+    fn filter_points(&mut self) -> Vec<(DropReason, usize)> {
+        let filter = self.conf_quality_filter;
+        let mut drops: Vec<(DropReason, usize)> = Vec::new();
+        let mut keep = vec![true; self.waypoints.len()];
+        // index of the previous point kept in the current track
+        let mut prev: Option<usize> = None;
+        // the point just before, if it was dropped for speed or jump
+        let mut prev_dropped: Option<usize> = None;
+        let mut carry_flags = 0u8;
+        for (i, kept) in keep.iter_mut().enumerate() {
+            let DatablockEnum::Datablock(wpt) = &self.waypoints[i] else {
+                continue;
+            };
+            let wpflags = wpt.wpflags;
+            if (wpflags | carry_flags) & 0x01 != 0 {
+                prev = None;
+                prev_dropped = None;
+            }
+            let datablock = |j: usize| match &self.waypoints[j] {
+                DatablockEnum::Datablock(wpt) => Some(wpt),
+                _ => None,
+            };
+            let mut result = filter.check(prev.and_then(datablock), wpt);
+            if matches!(result, Some(DropReason::Speed | DropReason::Jump))
+                && let Some(dropped) = prev_dropped.and_then(datablock)
+                && filter.check(Some(dropped), wpt).is_none()
+            {
+                // re-anchor after a gap
+                result = None;
+            }
+            prev_dropped = None;
+            match result {
+                Some(reason) => {
+                    *kept = false;
+                    if matches!(reason, DropReason::Speed | DropReason::Jump) {
+                        prev_dropped = Some(i);
+                    }
+                    match drops.iter_mut().find(|(r, _)| *r == reason) {
+                        Some((_, count)) => *count += 1,
+                        None => drops.push((reason, 1)),
+                    }
+                    if wpflags & 0x02 != 0 {
+                        if let Some(DatablockEnum::Datablock(prev_wpt)) =
+                            prev.map(|j| &mut self.waypoints[j])
+                        {
+                            prev_wpt.wpflags |= 0x02;
+                        }
+                        carry_flags = 0;
+                    } else {
+                        carry_flags |= wpflags;
+                    }
+                }
+                None => {
+                    if let DatablockEnum::Datablock(wpt) = &mut self.waypoints[i] {
+                        wpt.wpflags |= carry_flags;
+                    }
+                    carry_flags = 0;
+                    prev = Some(i);
+                }
+            }
+        }
+        let mut keep_iter = keep.iter();
+        self.waypoints.retain(|_| *keep_iter.next().unwrap());
+        let mut keep_iter = keep.iter();
+        self.offsets.retain(|_| *keep_iter.next().unwrap());
+
+        drops.sort();
+        let total: usize = drops.iter().map(|(_, count)| count).sum();
+        info!("  Quality filter dropped {total} points");
+        for (reason, count) in &drops {
+            info!("    {count} {reason}");
+        }
+        drops
     }
 
     fn transfer_flags_forward(&mut self) {
         let mut next_flags = 0u8;
         for wp in self.waypoints.iter_mut() {
             match wp {
-                DatablockEnum::NoBlock => {}
-                DatablockEnum::Button(_, typ) | DatablockEnum::ButtonWithoutTime(typ) => {
+                DatablockEnum::NoBlock
+                | DatablockEnum::WithoutPosition(..)
+                | DatablockEnum::Unknown { .. } => {}
+                DatablockEnum::Button(_, typ, _) | DatablockEnum::ButtonWithoutTime(typ) => {
                     match typ {
                         ButtonEnum::On => {
                             next_flags |= 0x01;
@@ -273,6 +1067,9 @@ impl Gt120bDataDump {
                         }
                     }
                 }
+                DatablockEnum::SwitchOff(_) => {
+                    next_flags = 0;
+                }
                 DatablockEnum::Datablock(wpt) => {
                     wpt.wpflags |= next_flags;
                     next_flags = 0;
@@ -281,14 +1078,19 @@ impl Gt120bDataDump {
         }
     }
     fn transfer_flags_backward(&mut self) {
+        let switch_off_points = self.conf_switch_off_points;
         let mut next_flags = 0u8;
+        let mut switch_off_time = None;
         for wp in self.waypoints.iter_mut().rev() {
             match wp {
-                DatablockEnum::NoBlock => {}
-                DatablockEnum::Button(_, typ) | DatablockEnum::ButtonWithoutTime(typ) => {
+                DatablockEnum::NoBlock
+                | DatablockEnum::WithoutPosition(..)
+                | DatablockEnum::Unknown { .. } => {}
+                DatablockEnum::Button(_, typ, _) | DatablockEnum::ButtonWithoutTime(typ) => {
                     match typ {
                         ButtonEnum::On => {
                             next_flags = 0;
+                            switch_off_time = None;
                         }
                         ButtonEnum::Off => {
                             next_flags |= 0x02;
@@ -296,33 +1098,64 @@ impl Gt120bDataDump {
                         ButtonEnum::Trigger => {}
                     }
                 }
+                DatablockEnum::SwitchOff(wpt) => {
+                    wpt.wpflags |= 0x02;
+                    next_flags |= 0x02;
+                    switch_off_time = Some(wpt.time);
+                }
                 DatablockEnum::Datablock(wpt) => {
+                    if switch_off_points && switch_off_time.is_some_and(|time| time != wpt.time) {
+                        // the switch-off point is exported and ends the track
+                        next_flags &= !0x02;
+                    }
                     wpt.wpflags |= next_flags;
                     next_flags = 0;
+                    switch_off_time = None;
                 }
             }
         }
     }
 
-    fn parse_data(&mut self, data: Vec<u8>) {
-        // TODO print offset for verbosity
+    fn parse_data(&mut self, offset: u32, data: Vec<u8>) {
         let mut pos = 0;
         while pos < data.len() {
-            trace!("< {:02X?}", &data[pos..(pos + 8)]);
+            let chunk_offset = offset + pos as u32;
+            let header = ChunkHeader::from_bytes(&data[pos..(pos + 8)]);
+            trace!("< {chunk_offset:06x}: {header}");
+            if let Err(err) = header.validate(&data[(pos + 8)..(pos + CHUNK_SIZE)]) {
+                warn!("Chunk at 0x{chunk_offset:06x} ({header}): {err}");
+                self.chunk_errors.push((chunk_offset, err));
+            }
             pos += 8;
             for _n in 0..4 {
-                trace!("< {:02X?}", &data[pos..(pos + 30)]);
-                let wp = parse_datablock(data[pos..(pos + 30)].to_vec());
+                trace!("< {:02X?}", &data[pos..(pos + RECORD_SIZE)]);
+                let wp =
+                    parse_datablock(offset + pos as u32, data[pos..(pos + RECORD_SIZE)].to_vec());
                 if !matches!(wp, DatablockEnum::NoBlock) {
+                    let record_offset = offset + pos as u32;
+                    if let Some(time) = wp.time_opt()
+                        && let Some(prev) = self.waypoints.iter().rev().find_map(|wp| wp.time_opt())
+                        && time < prev
+                    {
+                        warn!("Record at 0x{record_offset:06x}: time {time} is before {prev}");
+                        self.out_of_order.push(record_offset);
+                    }
                     self.waypoints.push(wp);
+                    self.offsets.push(record_offset);
                 }
-                pos += 30;
+                pos += RECORD_SIZE;
             }
         }
     }
 }
 
-fn parse_datablock(value: Vec<u8>) -> DatablockEnum {
+/// Time of the first record of a chunk read at flash offset `offset`. `None` if the record is empty or has no time
+pub fn first_record_time(offset: u32, chunk: &[u8]) -> Option<DateTime<Utc>> {
+    parse_datablock(offset + 8, chunk[8..(8 + RECORD_SIZE)].to_vec()).time_opt()
+}
+
+/// Decodes one record read at flash offset `offset`. Records which can't be decoded are returned as `Unknown`
+fn parse_datablock(offset: u32, value: Vec<u8>) -> DatablockEnum {
     let flagfield = value[0];
     if flagfield == 0xff {
         // empty data
@@ -331,10 +1164,11 @@ fn parse_datablock(value: Vec<u8>) -> DatablockEnum {
     if flagfield == 0x02 {
         return DatablockEnum::ButtonWithoutTime(ButtonEnum::Off);
     }
-    let flagfield = flagfield & !0x20; // unsure what is 0x20, but it is sometimes there and sometimes not
-    if flagfield == 0x50 {
-        // block without coordinates
-        return DatablockEnum::NoBlock;
+    let flag_20 = flagfield & 0x20 != 0; // unsure what is 0x20, but it is sometimes there and sometimes not
+    let flagfield = flagfield & !0x20;
+    if !matches!(flagfield, 0x00 | 0x41 | 0x42 | 0x43 | 0x50) {
+        trace!("Unknown data flags: {flagfield:02x} in {value:02x?}");
+        return DatablockEnum::Unknown { offset, raw: value };
     }
 
     let ymd = u32::from_be_bytes(value[2..6].try_into().unwrap());
@@ -347,24 +1181,27 @@ fn parse_datablock(value: Vec<u8>) -> DatablockEnum {
     let mon = ymd >> 16 & 0xf;
     let year = 2000 + value[2] as i32;
 
-    println!("{year:04}-{mon:02}-{day:02}T{hour:02}:{mins:02}:{secs:02} {msecs:3} >> {value:02x?}");
+    trace!("{year:04}-{mon:02}-{day:02}T{hour:02}:{mins:02}:{secs:02} {msecs:3} >> {value:02x?}");
 
-    let time = utc_dt_from_ymd_hms_milli(year, mon, day, hour, mins, secs, msecs);
+    let Some(time) = NaiveDate::from_ymd_opt(year, mon, day)
+        .and_then(|date| date.and_hms_milli_opt(hour, mins, secs, msecs))
+        .map(|time| time.and_utc())
+    else {
+        trace!("Invalid time in {value:02x?}");
+        return DatablockEnum::Unknown { offset, raw: value };
+    };
 
     if flagfield == 0x41 {
         // new track, no geo
-        return DatablockEnum::Button(time, ButtonEnum::On);
-    }
-    if flagfield == 0x42 {
-        // switch-off. geo but no waypoint
-        return DatablockEnum::Button(time, ButtonEnum::Off); // we could dump this. but orig sw ignores this coords and only takes the flag
+        return DatablockEnum::Button(time, ButtonEnum::On, flag_20);
     }
     if flagfield == 0x43 {
         // button pressed, no geo
-        return DatablockEnum::Button(time, ButtonEnum::Trigger);
+        return DatablockEnum::Button(time, ButtonEnum::Trigger, flag_20);
     }
-    if flagfield != 0x00 {
-        panic!("Unknown data flags: {flagfield:02x} in {value:02x?}")
+    if flagfield == 0x50 {
+        // block without coordinates
+        return DatablockEnum::WithoutPosition(time, flag_20);
     }
 
     let sat_used = value[1] & 0x0f;
@@ -372,11 +1209,11 @@ fn parse_datablock(value: Vec<u8>) -> DatablockEnum {
     let course = u16::from_le_bytes(value[28..30].try_into().unwrap()) as f32 / 100.0;
     let speed = u16::from_le_bytes(value[26..28].try_into().unwrap()) as f32 / 100.0;
     let hdop = u16::from_le_bytes(value[8..10].try_into().unwrap()) as f32 / 10.0;
-    let ele = i32::from_le_bytes(value[22..26].try_into().unwrap()) as f32 / 100.0;
-    let lat = i32::from_le_bytes(value[14..18].try_into().unwrap()) as f32 / 10000000.0;
-    let lon = i32::from_le_bytes(value[18..22].try_into().unwrap()) as f32 / 10000000.0;
+    let ele_cm = i32::from_le_bytes(value[22..26].try_into().unwrap());
+    let lat_e7 = i32::from_le_bytes(value[14..18].try_into().unwrap());
+    let lon_e7 = i32::from_le_bytes(value[18..22].try_into().unwrap());
 
-    DatablockEnum::Datablock(Waypoint {
+    let wpt = Waypoint {
         time,
         wpflags: 0,
         sat_used,
@@ -384,13 +1221,20 @@ fn parse_datablock(value: Vec<u8>) -> DatablockEnum {
         course,
         speed,
         hdop,
-        ele,
-        lat,
-        lon,
-    })
+        ele_cm,
+        lat_e7,
+        lon_e7,
+        flag_20,
+    };
+    if flagfield == 0x42 {
+        // switch-off, with the last fix. The original software only takes the flag and ignores the position
+        return DatablockEnum::SwitchOff(wpt);
+    }
+    DatablockEnum::Datablock(wpt)
 }
 
-fn utc_dt_from_ymd_hms_milli(
+#[cfg(test)]
+pub(crate) fn utc_dt_from_ymd_hms_milli(
     y: i32,
     mo: u32,
     d: u32,
@@ -406,6 +1250,27 @@ fn utc_dt_from_ymd_hms_milli(
         .and_utc()
 }
 
+/**
+ Track point for tests: 4 of 10 satellites, HDOP 4.2, 439.70 m, 1.15 m/s, 78.85° in Zurich. Other values with struct
+ update syntax, e.g. `Waypoint { hdop: 53.5, ..test_waypoint(time) }`
+*/
+#[cfg(test)]
+pub(crate) fn test_waypoint(time: DateTime<Utc>) -> Waypoint {
+    Waypoint {
+        time,
+        wpflags: 0,
+        flag_20: false,
+        sat_used: 4,
+        sat_visib: 10,
+        course: 78.85,
+        speed: 1.15,
+        hdop: 4.2,
+        ele_cm: 43970,
+        lat_e7: 473666840,
+        lon_e7: 85483980,
+    }
+}
+
 #[cfg(test)]
 #[allow(non_snake_case)]
 mod tests {
@@ -416,7 +1281,7 @@ mod tests {
     fn parse_datablock_NoBlock_goodcase() {
         let input=hex!["ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff"].to_vec();
 
-        let result = parse_datablock(input);
+        let result = parse_datablock(0x001000, input);
 
         println!("{:?}", result);
         assert!(matches!(result, DatablockEnum::NoBlock));
@@ -426,37 +1291,55 @@ mod tests {
     fn parse_datablock_NextMod_switchOn() {
         let input=hex!["41 a0 19 07 fd 05 f4 15 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 3a c0 00 00 00 00 00 00"].to_vec();
 
-        let result = parse_datablock(input);
+        let result = parse_datablock(0x001000, input);
 
         println!("{:?}", result);
-        assert!(matches!(result, DatablockEnum::Button(_, _)));
-        let DatablockEnum::Button(time, typ) = result else {
+        let DatablockEnum::Button(time, typ, flag_20) = result else {
             panic!("Invalid result type")
         };
         assert_eq!(time, utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 5, 5, 620));
         assert_eq!(typ, ButtonEnum::On);
+        assert!(!flag_20);
+    }
+
+    #[test]
+    fn parse_datablock_NextMod_switchOn_flag_20() {
+        let input=hex!["61 a0 19 07 fd 05 f4 15 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 3a c0 00 00 00 00 00 00"].to_vec();
+
+        let result = parse_datablock(0x001000, input);
+
+        let DatablockEnum::Button(time, typ, flag_20) = result else {
+            panic!("Invalid result type")
+        };
+        assert_eq!(time, utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 5, 5, 620));
+        assert_eq!(typ, ButtonEnum::On);
+        assert!(flag_20);
     }
 
     #[test]
     fn parse_datablock_PrevMod_switchOff() {
         let input=hex!["42 a6 19 07 fd 08 69 b5 2a 00 00 00 00 00 2c 95 3b 1c ce 55 18 05 b6 a3 00 00 19 00 c0 0f"].to_vec();
 
-        let result = parse_datablock(input);
+        let result = parse_datablock(0x001000, input);
 
         println!("{:?}", result);
-        assert!(matches!(result, DatablockEnum::Button(_, _)));
-        let DatablockEnum::Button(time, typ) = result else {
+        let DatablockEnum::SwitchOff(wpt) = result else {
             panic!("Invalid result type")
         };
-        assert_eq!(time, utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 46, 441));
-        assert_eq!(typ, ButtonEnum::Off);
+        assert_eq!(
+            wpt.time,
+            utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 46, 441)
+        );
+        assert_eq!(wpt.lat_e7, 473666860);
+        assert_eq!(wpt.lon_e7, 85480910);
+        assert_eq!(wpt.ele_cm, 41910);
     }
 
     #[test]
     fn parse_datablock_Datablock_goodcase() {
         let input=hex!["00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"].to_vec();
 
-        let result = parse_datablock(input);
+        let result = parse_datablock(0x001000, input);
 
         println!("{:?}", result);
         assert!(matches!(result, DatablockEnum::Datablock(_)));
@@ -473,16 +1356,94 @@ mod tests {
         assert_eq!(wpt.course, 78.85);
         assert_eq!(wpt.speed, 1.15);
         assert_eq!(wpt.hdop, 4.2);
-        assert_eq!(wpt.ele, 439.7);
-        assert_eq!(wpt.lat, 47.366684);
-        assert_eq!(wpt.lon, 8.548398);
+        assert_eq!(wpt.ele_cm, 43970);
+        assert_eq!(wpt.lat_e7, 473666860);
+        assert_eq!(wpt.lon_e7, 85483980);
+        assert_eq!(wpt.ele(), 439.7);
+        assert_eq!(wpt.lat(), 47.366686); // was 47.366684 with f32
+        assert_eq!(wpt.lon(), 8.548398);
+    }
+
+    #[test]
+    fn parse_datablock_Datablock_flag_20() {
+        let input=hex!["20 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"].to_vec();
+
+        let DatablockEnum::Datablock(wpt) = parse_datablock(0x001000, input) else {
+            panic!("Invalid result type")
+        };
+        assert!(wpt.flag_20);
+        assert_eq!(wpt.lat_e7, 473666860);
+    }
+
+    #[test]
+    fn parse_datablock_WithoutPosition() {
+        let input=hex!["50 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"].to_vec();
+
+        let result = parse_datablock(0x001000, input);
+
+        assert_eq!(
+            result.time(),
+            utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441)
+        );
+        assert!(matches!(result, DatablockEnum::WithoutPosition(_, false)));
+    }
+
+    #[test]
+    fn parse_datablock_Unknown() {
+        let unknown_flags=hex!["13 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"].to_vec();
+        let invalid_month=hex!["00 a4 19 00 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"].to_vec();
+
+        for input in [unknown_flags, invalid_month] {
+            let result = parse_datablock(0x00104a, input.clone());
+
+            assert_eq!(result.time_opt(), None);
+            let DatablockEnum::Unknown { offset, raw } = result else {
+                panic!("Invalid result type")
+            };
+            assert_eq!(offset, 0x00104a);
+            assert_eq!(raw, input);
+        }
+    }
+
+    #[test]
+    fn parse_datablock_Datablock_raw_coordinates() {
+        // from doc/topics/dataformat-parsing/data-structure.txt
+        let input=hex!["00 d5 19 07 fc 48 00 00 0e 00 7d 3b 00 00 a2 a5 3c 1c 6c 83 15 05 08 c0 00 00 5a 00 49 1d"].to_vec();
+
+        let result = parse_datablock(0x001000, input);
+
+        let DatablockEnum::Datablock(wpt) = result else {
+            panic!("Invalid result type")
+        };
+        // bytes 14..18, 18..22 and 22..26, little endian
+        assert_eq!(wpt.lat_e7, 0x1c3ca5a2);
+        assert_eq!(wpt.lon_e7, 0x0515836c);
+        assert_eq!(wpt.ele_cm, 0xc008);
+        assert_eq!(wpt.lat(), 47.373661);
+        assert_eq!(wpt.lon(), 8.529598);
+        assert_eq!(wpt.ele(), 491.6);
+    }
+
+    #[test]
+    fn waypoint_lossless_large_longitude() {
+        let wpt = Waypoint {
+            ele_cm: -1234,
+            lat_e7: -899999999,
+            lon_e7: 1799999999,
+            ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441))
+        };
+
+        // f32 would round these to -90 and 180
+        assert_eq!(format!("{:.7}", wpt.lat()), "-89.9999999");
+        assert_eq!(format!("{}", wpt.lon()), "179.9999999");
+        assert_eq!(wpt.ele(), -12.34);
     }
 
     #[test]
     fn parse_datablock_Datablock_wide_hdop() {
         let input=hex!["00 a4 19 07 fd 08 99 ad 17 02 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"].to_vec();
 
-        let result = parse_datablock(input);
+        let result = parse_datablock(0x001000, input);
 
         println!("{:?}", result);
         assert!(matches!(result, DatablockEnum::Datablock(_)));
@@ -499,66 +1460,391 @@ mod tests {
         assert_eq!(wpt.course, 78.85);
         assert_eq!(wpt.speed, 1.15);
         assert_eq!(wpt.hdop, 53.5); // not 2.3!
-        assert_eq!(wpt.ele, 439.7);
-        assert_eq!(wpt.lat, 47.366684);
-        assert_eq!(wpt.lon, 8.548398);
+        assert_eq!(wpt.ele_cm, 43970);
+        assert_eq!(wpt.lat_e7, 473666860);
+        assert_eq!(wpt.lon_e7, 85483980);
+        assert_eq!(wpt.ele(), 439.7);
+        assert_eq!(wpt.lat(), 47.366686); // was 47.366684 with f32
+        assert_eq!(wpt.lon(), 8.548398);
     }
 
     #[test]
     fn parse_datablock_NextMod_button() {
         let input=hex!["43 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"].to_vec();
 
-        let result = parse_datablock(input);
+        let result = parse_datablock(0x001000, input);
 
         println!("{:?}", result);
-        assert!(matches!(result, DatablockEnum::Button(_, _)));
-        let DatablockEnum::Button(time, typ) = result else {
+        let DatablockEnum::Button(time, typ, flag_20) = result else {
             panic!("Invalid result type")
         };
         assert_eq!(time, utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 7, 57, 457));
         assert_eq!(typ, ButtonEnum::Trigger);
+        assert!(!flag_20);
     }
 
     #[test]
-    fn dump() {
-        let input = Waypoint {
-            time: utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441),
-            wpflags: 18,
-            sat_used: 4,
-            sat_visib: 10,
-            course: 78.85,
-            speed: 1.15,
-            hdop: 4.2,
-            ele: 439.7,
-            lat: 47.366684,
-            lon: 8.548398,
+    fn parse_datablock_NextMod_button_flag_20() {
+        let input=hex!["63 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"].to_vec();
+
+        let result = parse_datablock(0x001000, input);
+
+        let DatablockEnum::Button(time, typ, flag_20) = result else {
+            panic!("Invalid result type")
+        };
+        assert_eq!(time, utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 7, 57, 457));
+        assert_eq!(typ, ButtonEnum::Trigger);
+        assert!(flag_20);
+    }
+
+    #[test]
+    fn split_policy_from_str() {
+        assert_eq!("single".parse(), Ok(SplitPolicy::Single));
+        assert_eq!("track".parse(), Ok(SplitPolicy::Track));
+        assert_eq!("day".parse(), Ok(SplitPolicy::Day));
+        assert_eq!("6h".parse(), Ok(SplitPolicy::Hours(6)));
+        assert!("0h".parse::<SplitPolicy>().is_err());
+        assert!("48h".parse::<SplitPolicy>().is_err());
+        assert!("5h".parse::<SplitPolicy>().is_err());
+        assert_eq!("24h".parse(), Ok(SplitPolicy::Hours(24)));
+        assert!("week".parse::<SplitPolicy>().is_err());
+        assert_eq!(SplitPolicy::Hours(6).to_string(), "6h");
+        assert!(SplitPolicy::Track.change_every_day() && SplitPolicy::Day.change_every_day());
+        assert!(!SplitPolicy::Single.change_every_day());
+    }
+
+    /// Tracks at 01:00, 05:00 and 07:00 on one day and at 02:00 on the next, each with a switch-on and two points
+    fn dump_four_tracks(name: &str) -> (Gt120bDataDump, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("igotu-split-{name}-{}", std::process::id()));
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        dump.set_file_names(
+            dir.clone(),
+            "{start:%Y%m%d%H}.{ext}".parse().unwrap(),
+            CollisionPolicy::Overwrite,
+        );
+        dump.set_timezone(Some(Timezone::Named(chrono_tz::Tz::UTC)));
+        for (mon, day, hour) in [(7, 31, 1), (7, 31, 5), (7, 31, 7), (8, 1, 2)] {
+            let time = |min| utc_dt_from_ymd_hms_milli(2025, mon, day, hour, min, 0, 0);
+            dump.waypoints
+                .push(DatablockEnum::Button(time(0), ButtonEnum::On, false));
+            for min in [1, 2] {
+                dump.waypoints
+                    .push(DatablockEnum::Datablock(test_waypoint(time(min))));
+            }
+        }
+        dump.offsets = (0..dump.waypoints.len() as u32)
+            .map(|i| 0x1008 + i * 0x1e)
+            .collect();
+        (dump, dir)
+    }
+
+    #[test]
+    fn write_out_split_day() {
+        let (mut dump, dir) = dump_four_tracks("day");
+
+        let files = dump
+            .write_out(SplitPolicy::Day, ExportFormat::Gpx, "")
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = files.iter().map(|f| f.path.file_name().unwrap()).collect();
+        assert_eq!(names, ["2025073101.gpx", "2025080102.gpx"]);
+        let points: Vec<_> = files.iter().map(|f| f.points).collect();
+        assert_eq!(points, [6, 2]);
+    }
+
+    #[test]
+    fn write_out_split_hours() {
+        let (mut dump, dir) = dump_four_tracks("hours");
+
+        let files = dump
+            .write_out(SplitPolicy::Hours(6), ExportFormat::Gpx, "")
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = files.iter().map(|f| f.path.file_name().unwrap()).collect();
+        assert_eq!(
+            names,
+            ["2025073101.gpx", "2025073107.gpx", "2025080102.gpx"]
+        );
+        let points: Vec<_> = files.iter().map(|f| f.points).collect();
+        assert_eq!(points, [4, 2, 2]);
+    }
+
+    #[test]
+    fn write_out_poi_file_only() {
+        let (mut dump, dir) = dump_four_tracks("poi");
+        dump.waypoints[2] = DatablockEnum::Button(
+            utc_dt_from_ymd_hms_milli(2025, 7, 31, 1, 2, 0, 0),
+            ButtonEnum::Trigger,
+            false,
+        );
+        dump.set_button_waypoints(Some(ButtonWaypoints {
+            sym: "Flag, Blue".to_string(),
+            in_tracks: false,
+            poi_file: true,
+        }));
+
+        let files = dump
+            .write_out(SplitPolicy::Single, ExportFormat::Gpx, "")
+            .unwrap();
+        let track_file = std::fs::read_to_string(&files[0].path).unwrap();
+        let poi_file = std::fs::read_to_string(&files[1].path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert!(!track_file.contains("<wpt "), "{track_file}");
+        assert_eq!(poi_file.matches("<wpt ").count(), 1);
+    }
+
+    #[test]
+    fn tracks_split_at_switch_on_only() {
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        dump.process_datablock(0x001000, hex!["0c 00 41 02 5a 1c 39 e1
+            41 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            41 a6 19 07 fd 08 69 b5 2a 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 09 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"]
+            .to_vec());
+        dump.prepare_data();
+
+        let tracks = dump.tracks(false);
+
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].points().count(), 1);
+        assert_eq!(tracks[1].points().count(), 1);
+    }
+
+    #[test]
+    fn out_of_order_records_are_kept() {
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        dump.process_datablock(0x001000, hex!["0c 00 41 02 5a 1c 39 e1
+            41 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 09 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            00 a4 19 07 fd 0a 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"]
+            .to_vec());
+        dump.prepare_data();
+
+        assert_eq!(dump.out_of_order(), &[0x001044]);
+        assert_eq!(dump.num_points(), 3);
+    }
+
+    #[test]
+    fn tracks_switch_off_points() {
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        dump.process_datablock(0x001000, hex!["0c 00 41 02 5a 1c 39 e1
+            41 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            42 a6 19 07 fd 08 69 b5 2a 00 00 00 00 00 2c 95 3b 1c ce 55 18 05 b6 a3 00 00 19 00 c0 0f
+            ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff"]
+            .to_vec());
+        dump.prepare_data();
+
+        let tracks = dump.tracks(false);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].points().count(), 1);
+        assert_eq!(
+            tracks[0].switch_on,
+            Some(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 7, 57, 457))
+        );
+        assert_eq!(
+            tracks[0].switch_off,
+            Some(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 46, 441))
+        );
+        assert_eq!(dump.num_points(), 1);
+
+        dump.set_switch_off_points(true);
+        let tracks = dump.tracks(false);
+        let points: Vec<&Waypoint> = tracks[0].points().collect();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].lon_e7, 85480910);
+        assert_eq!(points[1].wpflags, 0x02);
+        assert_eq!(dump.num_points(), 2);
+    }
+
+    #[test]
+    fn filter_points_keeps_track_flags() {
+        let waypoint = |min, wpflags, hdop, sat_used| {
+            DatablockEnum::Datablock(Waypoint {
+                wpflags,
+                sat_used,
+                hdop,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, min, 0, 0))
+            })
+        };
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        dump.waypoints = vec![
+            waypoint(0, 0x01, 53.5, 4),
+            waypoint(1, 0, 1.0, 4),
+            waypoint(2, 0x02, 1.0, 4),
+            waypoint(3, 0x01, 1.0, 4),
+            waypoint(4, 0x02, 1.0, 2),
+        ];
+        dump.offsets = vec![0x1008, 0x1026, 0x1044, 0x1062, 0x1088];
+        dump.set_quality_filter(QualityFilter {
+            max_hdop: Some(10.0),
+            min_sats: Some(3),
+            ..Default::default()
+        });
+
+        let drops = dump.filter_points();
+
+        assert_eq!(drops, [(DropReason::Hdop, 1), (DropReason::Satellites, 1)]);
+        assert_eq!(dump.offsets, [0x1026, 0x1044, 0x1062]);
+        let tracks = dump.tracks(false);
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].points().count(), 2);
+        let flags: Vec<u8> = tracks
+            .iter()
+            .flat_map(|t| t.points())
+            .map(|w| w.wpflags)
+            .collect();
+        assert_eq!(flags, [0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn filter_points_after_gap() {
+        let waypoint = |min, lat_e7, wpflags| {
+            DatablockEnum::Datablock(Waypoint {
+                wpflags,
+                lat_e7,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, min, 0, 0))
+            })
         };
-        let buf = Vec::<u8>::new();
-        let mut writer = BufWriter::new(buf);
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        // 100 m per minute, then a tunnel: 5.5 km in 5 minutes, then 100 m per minute again
+        dump.waypoints = vec![
+            waypoint(0, 473660000, 0x01),
+            waypoint(1, 473669000, 0),
+            waypoint(6, 474169000, 0),
+            waypoint(7, 474178000, 0),
+            waypoint(8, 474187000, 0x02),
+        ];
+        dump.offsets = vec![0x1008, 0x1026, 0x1044, 0x1062, 0x1088];
+        dump.set_quality_filter(QualityFilter {
+            max_jump_m: Some(1000.0),
+            ..Default::default()
+        });
+
+        let drops = dump.filter_points();
+
+        assert_eq!(drops, [(DropReason::Jump, 1)]);
+        assert_eq!(dump.offsets, [0x1008, 0x1026, 0x1062, 0x1088]);
+        assert_eq!(dump.tracks(false)[0].points().count(), 4);
+    }
+
+    #[test]
+    fn tracks_day_change_in_timezone() {
+        let waypoint = |hour, min, wpflags| {
+            DatablockEnum::Datablock(Waypoint {
+                wpflags,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, hour, min, 0, 0))
+            })
+        };
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        // 20:00 and 23:00 CEST, then 00:30 CEST of the next day (22:30 UTC, same day in UTC)
+        dump.waypoints = vec![
+            waypoint(18, 0, 0x01),
+            waypoint(21, 0, 0),
+            waypoint(22, 30, 0),
+        ];
+        dump.set_timezone(Some(Timezone::Named(chrono_tz::Tz::Europe__Zurich)));
+
+        let tracks = dump.tracks(true);
+
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[1].points().count(), 1);
+        assert_eq!(
+            dump.file_time(dump.zone(), &tracks[1].start_time()),
+            "2025-08-01_00-30"
+        );
+
+        dump.set_timezone(Some(Timezone::Named(chrono_tz::Tz::UTC)));
+        assert_eq!(dump.tracks(true).len(), 1);
+    }
 
-        DatablockEnum::Datablock(input).dump(&mut writer).unwrap();
+    #[test]
+    fn chunk_header_validate() {
+        // from doc/topics/dataformat-parsing/data-structure.txt
+        let chunk = hex!["0c 00 41 02 5a 1c 39 e1
+            43 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            42 a6 19 07 fd 08 69 b5 2a 00 00 00 00 00 2c 95 3b 1c ce 55 18 05 b6 a3 00 00 19 00 c0 0f
+            ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff"];
+        let header = ChunkHeader::from_bytes(&chunk);
+        let erased = [0xffu8; CHUNK_SIZE];
+        let records = |chunk: &[u8]| chunk[8..].to_vec();
+
+        assert_eq!(header.magic, CHUNK_MAGIC);
+        assert_eq!(header.tag, [0x5a, 0x1c, 0x39, 0xe1]);
+        assert_eq!(header.to_string(), "0c004102 5a1c39e1");
+        assert_eq!(header.validate(&records(&chunk)), Ok(()));
+        let erased_header = ChunkHeader::from_bytes(&erased);
+        assert!(erased_header.is_erased());
+        assert_eq!(erased_header.validate(&records(&erased)), Ok(()));
+        assert_eq!(
+            erased_header.validate(&records(&chunk)),
+            Err(ChunkError::ErasedWithRecords)
+        );
+        assert_eq!(
+            header.validate(&records(&erased)),
+            Err(ChunkError::HeaderWithoutRecords)
+        );
+        let mut gap = chunk;
+        gap[8 + RECORD_SIZE] = 0xff;
+        assert_eq!(
+            header.validate(&records(&gap)),
+            Err(ChunkError::RecordAfterEmpty(2))
+        );
+        let other = ChunkHeader::from_bytes(&hex!["0c 00 42 02 00 00 00 00"]);
+        assert_eq!(
+            other.validate(&records(&chunk)),
+            Err(ChunkError::UnknownMagic([0x0c, 0x00, 0x42, 0x02]))
+        );
+    }
 
-        let s = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    #[test]
+    fn process_datablock_chunk_errors() {
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        let mut data = [0xffu8; 2 * CHUNK_SIZE];
+        // second chunk: records, but erased header
+        data[CHUNK_SIZE + 8..CHUNK_SIZE + 8 + RECORD_SIZE].copy_from_slice(&hex![
+            "00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e"
+        ]);
+
+        dump.process_datablock(0x001000, data.to_vec());
+
+        assert_eq!(
+            dump.chunk_errors(),
+            &[(0x001080, ChunkError::ErasedWithRecords)]
+        );
+        // parsed anyway
+        assert_eq!(dump.waypoints().len(), 1);
+    }
+
+    #[test]
+    fn prepare_data_skip_until() {
+        let mut dump = Gt120bDataDump::new("".to_string(), "".to_string());
+        dump.process_datablock(0x001000, hex!["0c 00 41 02 5a 1c 39 e1
+            43 a0 19 07 fd 07 71 e0 00 00 0c 00 00 19 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+            00 a4 19 07 fd 08 99 ad 2a 00 2b 35 00 19 2c 95 3b 1c cc 61 18 05 c2 ab 00 00 73 00 cd 1e
+            42 a6 19 07 fd 08 69 b5 2a 00 00 00 00 00 2c 95 3b 1c ce 55 18 05 b6 a3 00 00 19 00 c0 0f
+            ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff"]
+            .to_vec());
+        dump.set_skip_until(Some(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 0, 0)));
+
+        dump.prepare_data();
+
+        assert_eq!(dump.waypoints().len(), 2);
+        assert!(matches!(dump.waypoints()[0], DatablockEnum::Datablock(_)));
+        assert_eq!(
+            dump.records().map(|(offset, _)| offset).collect::<Vec<_>>(),
+            vec![0x001026, 0x001044]
+        );
         assert_eq!(
-            s,
-            "      \
-      <trkpt lat=\"47.366684\" lon=\"8.548398\">
-        <ele>439.7</ele>
-        <time>2025-07-31T20:08:44.441Z</time>
-        <type>WpFlag:18</type>
-        <sat>4</sat>
-        <hdop>4.2</hdop>
-        <extensions>
-          <gpxtpx:TrackPointExtension>
-            <gpxtpx:speed>1.15</gpxtpx:speed>
-            <gpxtpx:course>78.85</gpxtpx:course>
-          </gpxtpx:TrackPointExtension>
-          <mat:TrackPointExtension>
-            <mat:sat_view>10</mat:sat_view>
-          </mat:TrackPointExtension>
-        </extensions>
-      </trkpt>
-"
+            dump.last_time(),
+            Some(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 44, 441))
         );
     }
 }
@@ -1,161 +1,523 @@
 use crate::comm_bulk::CommBulk;
 use crate::commands::IdentificationJson;
 use crate::commands::{
-    Model, cmd_count, cmd_delete_reboot, cmd_identification, cmd_model, cmd_nmea_switch, cmd_read,
-    cmd_set_time,
+    Model, cmd_count, cmd_delete_reboot, cmd_identification, cmd_model, cmd_nmea_switch,
+    cmd_page_program, cmd_read, cmd_read_status, cmd_sector_erase, cmd_set_time, cmd_write_enable,
 };
-use crate::gt120b_datadump::Gt120bDataDump;
+use crate::device_config::{CONFIG_POS, CONFIG_SIZE, DeviceConfig};
+use crate::download_state::{DeviceDownloadState, DownloadState, FIRST_DATA_OFFSET};
+use crate::error::{Error, Result};
+use crate::export_gpx::ButtonWaypoints;
+use crate::export_verify::verify_export;
+use crate::file_name::{CollisionPolicy, FileNameTemplate, OutputFile};
+use crate::gt120b_datadump::{
+    CHUNK_SIZE, ExportFormat, ExportedFile, Gt120bDataDump, SplitPolicy, first_record_time,
+};
+pub use crate::probe_state::STATE_PROBE_POS;
+use crate::probe_state::{ProbeState, STATE_PROBE_SIZE};
+use crate::quality_filter::QualityFilter;
+use crate::raw_dump::{RawDump, RawDumpHeader};
+use crate::timezone::Timezone;
+use crate::track_stats::Summary;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
-use log::{debug, info, trace};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, trace, warn};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
-pub fn workflow(
-    comm: &mut CommBulk,
-    conf_clear: bool,
-    conf_orig_sw_workflow: bool,
-    conf_orig_sw_meta: bool,
-    conf_prefix: String,
-    conf_suffix: String,
-) {
+/// Options for [`workflow`]
+#[derive(Debug, Clone, Default)]
+pub struct WorkflowConfig {
+    /// Clear device memory after successfully downloading and writing gpx files
+    pub clear: bool,
+    /// Run some extra commands without known purpose to match replay file
+    pub orig_sw_workflow: bool,
+    /// Use the exact same meta format as the original software
+    pub orig_sw_meta: bool,
+    /// Write gpx files byte-compatible to the original software
+    pub orig_sw_gpx: bool,
+    /// Filename part on the left side of the date, including optional path
+    pub prefix: String,
+    /// Filename part on the right side of the date
+    pub suffix: String,
+    /// State file for incremental downloads. Only new data is read and exported
+    pub state_file: Option<String>,
+    /// Write all memory blocks read to this file, see [`RawDump`]
+    pub raw_dump_file: Option<String>,
+    /// Write the track statistics as JSON to this file, see [`Summary`]
+    pub stats_file: Option<String>,
+    /// Output file format
+    pub format: ExportFormat,
+    /// How tracks are distributed to files. `None`: default of the format
+    pub split: Option<SplitPolicy>,
+    /// Don't split a track at the local day change if it started less than this before. `None`: 4 hours
+    pub day_change_grace: Option<chrono::Duration>,
+    /// Write button presses as `<wpt>` into gpx files
+    pub button_waypoints: Option<ButtonWaypoints>,
+    /// Append the position of the switch-off record to each track, see [`Gt120bDataDump::set_switch_off_points`]
+    pub switch_off_points: bool,
+    /// Drop implausible track points before the export
+    pub quality_filter: QualityFilter,
+    /// Timezone for file names and day splitting. `None`: names in UTC, day splitting in the local timezone
+    pub timezone: Option<Timezone>,
+    /// Directory for the output files, prepended to the file names
+    pub output_dir: Option<PathBuf>,
+    /// File names, see [`FileNameTemplate`]. `None`: prefix, start time and suffix like the original software
+    pub name_template: Option<FileNameTemplate>,
+    /// What to do if an output file exists already
+    pub collision: CollisionPolicy,
+    /// Continue read-only (no time setting, no clearing) if the state at [`STATE_PROBE_POS`] is unknown
+    pub tolerate_unknown_state: bool,
+    /// Stop before exporting if records with unknown flags or inconsistent chunks were found, instead of skipping them
+    pub strict: bool,
+}
+
+/**
+ Full download session as done by the original software: identify, read config and all data blocks, write gpx files and optionally clear the device
+*/
+pub fn workflow(comm: &mut CommBulk, conf: &WorkflowConfig) -> Result<()> {
     // set line coding request - probably not needed
     //sync_send_control(handle, 0x21, 0x20 /* set line coding*/, 0, 0, "\x00\xc2\x01\x00\x00\x00\x08", 7, 2000 );
 
-    let (id_model, id_offset, mut id_struct) = cmdblock_identify(comm, conf_orig_sw_meta);
-    assert_eq!(id_model, Model::Gt120);
+    if conf.clear && conf.quality_filter.is_active() && conf.raw_dump_file.is_none() {
+        // filtered points are in no file, they would be lost
+        return Err(Error::InvalidConfig(
+            "--clear with a quality filter needs --dump-raw, the dropped points would be lost"
+                .to_string(),
+        ));
+    }
+
+    let (id_model, id_offset, mut id_struct) = cmdblock_identify(comm, conf.orig_sw_meta)?;
+    if id_model != Model::Gt120 {
+        return Err(Error::UnsupportedModel(id_model));
+    }
 
-    let read8_payload = cmd_read(comm, 0x1fff80, 0x0008); // from data dump of original software. no clue what is expected here // TODO force all FFs?
-    if read8_payload.len() == 8 && read8_payload == vec![0xff; 8] {
+    let read8_payload = cmd_read(comm, STATE_PROBE_POS, STATE_PROBE_SIZE)?; // from data dump of original software. no clue what is expected here
+    let probe_state = ProbeState::decode(&read8_payload);
+    if probe_state.is_empty() {
         // I don't really know why the time is sent here, but the original sw does too
-        let time_us = comm.get_time_micros();
-        cmd_set_time(comm, time_us); //  1753997870971000_u64
+        let time_us = comm.get_time_micros()?;
+        cmd_set_time(comm, time_us)?; //  1753997870971000_u64
+    } else if conf.tolerate_unknown_state {
+        // possibly this non-empty information is important. maybe a bad block list? so nothing is written to the device
+        warn!("Device state at 0x{STATE_PROBE_POS:06x} is {probe_state}");
+        warn!("Continuing read-only: the time is not set and the device is not cleared");
     } else {
-        // possibly this non-empty information is important. maybe a bad block list? fortunately or unfortunately, I've never seen this case
-        panic!("Unknown device state. needs more debugging/development");
+        error!("Device state at 0x{STATE_PROBE_POS:06x} is {probe_state}");
+        return Err(Error::UnknownDeviceState(read8_payload));
     }
 
-    if conf_orig_sw_workflow {
+    if conf.orig_sw_workflow {
         // this block was introduced because the original sw does these calls, and I want to have a 100% identical replay for quality reasons.
         // but actually, I don't know what is done here and why. maybe it's an artifact of the incremental algorighm of the original software
         // (if you don't delete your data, already loaded data get skipped on next read, with the help of a local state storage)
 
         // we don't know what to do, but at least check that the results match to what was before
-        let offset2 = cmd_count(comm);
-        assert_eq!(id_offset, offset2);
-        let read8_payload2 = cmd_read(comm, 0x1fff80, 0x0008);
-        assert_eq!(read8_payload, read8_payload2);
+        let offset2 = cmd_count(comm)?;
+        check_unchanged("count", &id_offset, &offset2)?;
+        let read8_payload2 = cmd_read(comm, STATE_PROBE_POS, STATE_PROBE_SIZE)?;
+        check_unchanged("state at 0x1fff80", &read8_payload, &read8_payload2)?;
     }
 
-    cmdblock_readconfig(comm, &mut id_struct);
+    let config = cmdblock_readconfig(comm)?;
+    id_struct.alias = config.alias.clone();
 
     {
-        let offset = cmd_count(comm);
-        assert_eq!(id_offset, offset);
+        let offset = cmd_count(comm)?;
+        check_unchanged("count", &id_offset, &offset)?;
     }
 
-    let (end_offset, all_begin_empty) = cmdblock_find_end_offset(comm, id_offset);
+    let (end_offset, all_begin_empty, search_blocks) = cmdblock_find_end_offset(comm, id_offset)?;
+    // the last block with data. it is possibly incomplete
+    let last_block_offset = if all_begin_empty {
+        end_offset.saturating_sub(0x1000).max(FIRST_DATA_OFFSET)
+    } else {
+        end_offset
+    };
+
+    let mut state_store = match &conf.state_file {
+        Some(state_file) => Some(DownloadState::load(state_file)?),
+        None => None,
+    };
+    let prev_state = state_store
+        .as_ref()
+        .map(|s| s.get(&id_struct.device_id))
+        .unwrap_or_default();
+    let start_offset = if prev_state.next_offset > last_block_offset {
+        info!("Device memory was cleared since the last download. Reading from the beginning");
+        FIRST_DATA_OFFSET
+    } else if prev_state.next_offset > FIRST_DATA_OFFSET
+        && !cmdblock_first_record_downloaded(comm, prev_state.last_waypoint_time)?
+    {
+        warn!(
+            "Device memory was cleared and written again since the last download. Reading from the beginning"
+        );
+        FIRST_DATA_OFFSET
+    } else {
+        prev_state.next_offset
+    };
 
     info!("Start downloading data");
-    let mut datadumper = Gt120bDataDump::new(conf_prefix, conf_suffix);
+    let mut datadumper = Gt120bDataDump::new(conf.prefix.clone(), conf.suffix.clone());
+    datadumper.set_skip_until(prev_state.last_waypoint_time);
+    datadumper.set_keep_raw(conf.raw_dump_file.is_some());
     let mut datadumper_ref = Some(&mut datadumper);
-    let mut offset = 0x1000;
+    let mut offset = start_offset;
     while offset < end_offset {
-        cmdblock_read_doublet(comm, offset, &mut datadumper_ref);
+        for (pos, data) in cmdblock_read_doublet(comm, offset)? {
+            if let Some(ref mut datadumper) = datadumper_ref {
+                datadumper.process_datablock(pos, data);
+            }
+        }
         offset += 0x1000;
     }
     trace!("offsets: {id_offset:06x} {end_offset:06x} {offset:06x}");
 
     if !all_begin_empty {
         // result is important in some usecases
-        let resp = cmd_read(comm, offset, 0x0100);
+        let resp = cmd_read(comm, offset, 0x0100)?;
         if let Some(ref mut datadumper) = datadumper_ref {
-            datadumper.process_datablock(resp);
+            datadumper.process_datablock(offset, resp);
         }
-        let resp = cmd_read(comm, offset + 0x000f80, 0x0080);
+        let resp = cmd_read(comm, offset + 0x000f80, 0x0080)?;
         if let Some(ref mut datadumper) = datadumper_ref {
-            datadumper.process_datablock(resp);
+            datadumper.process_datablock(offset + 0x000f80, resp);
         }
-        let resp = cmd_read(comm, offset + 0x000100, 0x0e80);
+        let resp = cmd_read(comm, offset + 0x000100, 0x0e80)?;
         if let Some(ref mut datadumper) = datadumper_ref {
-            datadumper.process_datablock(resp);
+            datadumper.process_datablock(offset + 0x000100, resp);
         }
     }
 
-    info!("Dumping to GPX");
+    if let (Some(raw_dump_file), Some(datadumper)) = (&conf.raw_dump_file, &datadumper_ref) {
+        let mut blocks = vec![
+            (CONFIG_POS, config.raw.clone()),
+            (STATE_PROBE_POS, read8_payload.clone()),
+        ];
+        blocks.extend_from_slice(&search_blocks);
+        blocks.extend_from_slice(datadumper.raw_blocks());
+        let raw_dump = RawDump {
+            header: RawDumpHeader {
+                identification: id_struct.clone(),
+                count_offset: id_offset,
+                probe_state: Some(probe_state.hex()),
+                search_blocks: search_blocks.len(),
+            },
+            blocks,
+        };
+        raw_dump.save(raw_dump_file)?;
+    }
 
     if let Some(ref mut datadumper) = datadumper_ref {
-        let conf_change_every_day: bool = true;
-        let meta_desc = if conf_orig_sw_meta {
-            let json_str_compact = serde_json::to_string(&id_struct).unwrap();
-            BASE64_STANDARD.encode(json_str_compact)
-        } else {
-            serde_json::to_string(&id_struct).unwrap() // TODO formatted output
-        };
-        let num_files = datadumper
-            .write_out(conf_change_every_day, &meta_desc)
-            .expect("Problem while exporting to gpx files");
-        if num_files == 0 {
+        let files = export(datadumper, &id_struct, &probe_state, conf)?;
+        if conf.clear && !files.is_empty() {
+            info!("Verifying {} files before clearing", files.len());
+            verify_export(&files, datadumper.num_points())?;
+        }
+
+        if let (Some(state_store), Some(state_file)) = (&mut state_store, &conf.state_file) {
+            state_store.set(
+                &id_struct.device_id,
+                DeviceDownloadState {
+                    next_offset: last_block_offset,
+                    last_waypoint_time: datadumper.last_time().or(prev_state.last_waypoint_time),
+                },
+            );
+            state_store.save(state_file)?;
+        }
+
+        if files.is_empty() {
             // stopping here, there was nothing saved, so there's nothing to delete
-            return;
+            return Ok(());
         }
     }
 
-    if !conf_clear {
+    if !conf.clear {
         // stopping here, rest is only for deleting
-        return;
+        return Ok(());
+    }
+    if !probe_state.is_empty() {
+        error!("Not clearing the device, the files are written");
+        return Err(Error::UnknownDeviceState(read8_payload));
     }
 
     info!("Delete device data");
-    cmd_delete_reboot(comm);
+    cmd_delete_reboot(comm)?;
 
     // here: device reboots itself without returning an answer. not that it will disconnect and needs to be reconnected afterwards for making sure the delete was successful
     info!("Waiting for device reconnect");
 
-    let (id2_model, _id2_offset, id2_struct) = cmdblock_identify(comm, conf_orig_sw_meta);
+    let (id2_model, _id2_offset, id2_struct) = cmdblock_identify(comm, conf.orig_sw_meta)?;
     // check everything except offset
-    assert_eq!(id_model, id2_model);
+    check_unchanged("model", &id_model, &id2_model)?;
     id_struct.alias = id2_struct.alias.clone(); // fix value for comparing in the following line
-    assert_eq!(id_struct, id2_struct);
+    check_unchanged("identification", &id_struct, &id2_struct)?;
+
+    let payload = cmd_read(comm, STATE_PROBE_POS, STATE_PROBE_SIZE)?; // from data dump of original software. no clue what is expected here
+    let probe_state = ProbeState::decode(&payload);
+    if !probe_state.is_empty() {
+        error!("Device state at 0x{STATE_PROBE_POS:06x} after clearing is {probe_state}");
+        return Err(Error::UnknownDeviceState(payload));
+    }
+
+    if let (Some(state_store), Some(state_file)) = (&mut state_store, &conf.state_file) {
+        let mut state = state_store.get(&id_struct.device_id);
+        state.next_offset = FIRST_DATA_OFFSET;
+        state_store.set(&id_struct.device_id, state);
+        state_store.save(state_file)?;
+    }
+
+    let time_us = comm.get_time_micros()?;
+    cmd_set_time(comm, time_us)
+}
+
+/**
+ Offline variant of [`workflow`]: parses a file written with `raw_dump_file` and writes gpx files. Returns the number of files written
+*/
+pub fn workflow_parse_raw(raw_dump_file: &str, conf: &WorkflowConfig) -> Result<usize> {
+    let raw_dump = RawDump::load(raw_dump_file)?;
+    let mut id_struct = raw_dump.header.identification.clone();
+    if let Some(config) = raw_dump.block_at(CONFIG_POS) {
+        id_struct.alias = DeviceConfig::from_bytes(config)?.alias;
+    }
+
+    let probe_state = ProbeState::decode(
+        raw_dump
+            .block_at(STATE_PROBE_POS)
+            .unwrap_or(&[0xff; STATE_PROBE_SIZE as usize]),
+    );
+    if !probe_state.is_empty() {
+        warn!("Device state at 0x{STATE_PROBE_POS:06x} was {probe_state}");
+    }
+
+    let mut datadumper = Gt120bDataDump::new(conf.prefix.clone(), conf.suffix.clone());
+    for (offset, data) in raw_dump.data_blocks() {
+        datadumper.process_datablock(*offset, data.clone());
+    }
+    let files = export(&mut datadumper, &id_struct, &probe_state, conf)?;
+    Ok(files.iter().filter(|file| file.written).count())
+}
 
-    let payload = cmd_read(comm, 0x1fff80, 0x0008); // from data dump of original software. no clue what is expected here // TODO force all FFs?
-    assert!(
-        payload.len() == 8 && payload == vec![0xff; 8],
-        "Unknown device state. needs more debugging"
+fn export(
+    datadumper: &mut Gt120bDataDump,
+    id_struct: &IdentificationJson,
+    probe_state: &ProbeState,
+    conf: &WorkflowConfig,
+) -> Result<Vec<ExportedFile>> {
+    if conf.strict {
+        let unknown = datadumper.unknown_records().count();
+        let chunk_errors = datadumper.chunk_errors().len();
+        let out_of_order = datadumper.out_of_order().len();
+        if unknown > 0 || chunk_errors > 0 || out_of_order > 0 {
+            return Err(Error::StrictParsing(format!(
+                "{unknown} unknown records, {chunk_errors} inconsistent chunks, {out_of_order} records out of order"
+            )));
+        }
+    }
+    info!("Dumping to {}", conf.format);
+    if let Some(grace) = conf.day_change_grace {
+        datadumper.set_day_change_grace(grace);
+    }
+    datadumper.set_button_waypoints(conf.button_waypoints.clone());
+    datadumper.set_orig_sw_gpx(conf.orig_sw_gpx);
+    datadumper.set_switch_off_points(conf.switch_off_points);
+    datadumper.set_quality_filter(conf.quality_filter);
+    datadumper.set_timezone(conf.timezone);
+    datadumper.set_device(
+        &id_struct.alias,
+        &id_struct.serial_number,
+        &id_struct.device_id,
+    );
+    datadumper.set_file_names(
+        conf.output_dir.clone().unwrap_or_default(),
+        conf.name_template
+            .clone()
+            .unwrap_or_else(|| FileNameTemplate::legacy(&conf.prefix, &conf.suffix)),
+        conf.collision,
     );
+    let split = conf.split.unwrap_or(conf.format.default_split());
+    let meta_desc = if conf.orig_sw_meta {
+        let json_str_compact = serde_json::to_string(id_struct).unwrap();
+        BASE64_STANDARD.encode(json_str_compact)
+    } else {
+        let mut meta = serde_json::to_value(id_struct).unwrap();
+        if !probe_state.is_empty() {
+            // unknown states are kept in the files, for analyzing them later
+            meta["ProbeState"] = probe_state.hex().into();
+        }
+        meta.to_string() // TODO formatted output
+    };
+    let files = datadumper.write_out(split, conf.format, &meta_desc)?;
+
+    // same grouping as in the files
+    let summary = Summary::new(&datadumper.tracks(split.change_every_day()));
+    if !summary.tracks.is_empty() {
+        for line in summary.to_string().lines() {
+            info!("{line}");
+        }
+    }
+    if let Some(stats_file) = &conf.stats_file {
+        summary.save(Path::new(stats_file))?;
+    }
+    Ok(files)
+}
 
-    let time_us = comm.get_time_micros();
-    cmd_set_time(comm, time_us);
+fn check_unchanged<T: PartialEq + std::fmt::Debug>(
+    what: &str,
+    before: &T,
+    after: &T,
+) -> Result<()> {
+    if before != after {
+        return Err(Error::StateMismatch(format!(
+            "{what} was {before:?}, now {after:?}"
+        )));
+    }
+    Ok(())
 }
 
-fn cmdblock_readconfig(comm: &mut CommBulk, id_struct: &mut IdentificationJson) {
-    let name_config_response = cmd_read(comm, 0x000000, 0x00ea);
+/**
+ Reads and decodes the configuration block. The alias is part of it
+*/
+pub fn cmdblock_readconfig(comm: &mut CommBulk) -> Result<DeviceConfig> {
+    let name_config_response = cmd_read(comm, CONFIG_POS, CONFIG_SIZE)?;
+    debug!("< {name_config_response:X?}");
 
-    let name = String::from_utf8_lossy(&name_config_response[16..48]); // is utf-8
-    let name = name.trim_end_matches('\0');
-    id_struct.alias = name.to_string();
-    println!("NAME: <{name}> {}", name.len());
-    println!("< {name_config_response:X?}");
-    println!("CONFIG: normal interval: {}s", name_config_response[4]);
-    println!(
+    let config = DeviceConfig::from_bytes(&name_config_response)?;
+    info!("NAME: <{}> {}", config.alias, config.alias.len());
+    info!("CONFIG: normal interval: {}s", config.log_interval_s);
+    info!(
         "CONFIG: smart tracking above {}kmh: {}s",
-        name_config_response[2/*or 11*/], name_config_response[8]
+        config.smart_tracking_speed_kmh, config.smart_tracking_interval_s
     );
-    //TODO there are some other values in this response:
-    //< 10:0e
-    //< 19:00:38:00:07:00:00:02
-    //< f0:a0:90:65:76:7b:91:65
-    //< 01:d8:ff:04:01:06:09:21:20:f5
+    Ok(config)
 }
 
-fn cmdblock_find_end_offset(comm: &mut CommBulk, id_offset: u32) -> (u32, bool) {
+const SECTOR_SIZE: u32 = 0x1000;
+const PAGE_SIZE: u16 = 0x0100;
+
+/**
+ Writes the configuration block. EXPERIMENTAL, see the write commands in commands.rs.
+
+ Flash memory can only be written after erasing a whole sector, so the sector holding the config is read first, erased
+ and written again page by page with the new config. Finally, the sector is read back and compared.
+ Before erasing, the original sector is saved to `backup_file`, so it can be restored if writing fails
+*/
+pub fn cmdblock_writeconfig(
+    comm: &mut CommBulk,
+    config: &DeviceConfig,
+    backup_file: &Path,
+) -> Result<()> {
+    let new_config = config.to_bytes()?;
+
+    let mut sector = cmdblock_read_sector(comm, CONFIG_POS)?;
+    let mut f = OutputFile::create(backup_file)?;
+    f.write_all(&sector)?;
+    f.commit()?;
+    info!(
+        "Backup of the config sector written to {}",
+        backup_file.display()
+    );
+    sector[..CONFIG_SIZE as usize].copy_from_slice(&new_config);
+
+    info!("Erase config sector");
+    cmd_write_enable(comm)?;
+    cmd_sector_erase(comm, CONFIG_POS)?;
+    wait_while_busy(comm)?;
+
+    for (i, page) in sector.chunks(PAGE_SIZE as usize).enumerate() {
+        if page.iter().all(|&b| b == 0xff) {
+            trace!("empty page. nothing to write");
+            continue;
+        }
+        cmd_write_enable(comm)?;
+        cmd_page_program(comm, CONFIG_POS + i as u32 * PAGE_SIZE as u32, page)?;
+        wait_while_busy(comm)?;
+    }
+
+    let readback = cmdblock_read_sector(comm, CONFIG_POS)?;
+    if readback != sector {
+        let pos = readback
+            .iter()
+            .zip(sector.iter())
+            .position(|(a, b)| a != b)
+            .unwrap_or_default();
+        return Err(Error::VerifyFailed(format!(
+            "config sector differs at {pos:04x}"
+        )));
+    }
+    info!("Config written and verified");
+    Ok(())
+}
+
+/**
+ Changes the alias and reads it back using [`cmdblock_readconfig`]. There is no known command for only rebooting the
+ device (the reboot command also deletes all data), so no reboot is done. See [`cmdblock_writeconfig`] for `backup_file`
+*/
+pub fn cmdblock_set_alias(
+    comm: &mut CommBulk,
+    alias: &str,
+    backup_file: &Path,
+) -> Result<DeviceConfig> {
+    DeviceConfig::check_alias(alias)?;
+
+    let mut config = cmdblock_readconfig(comm)?;
+    if config.alias == alias {
+        info!("Alias is already <{alias}>");
+        return Ok(config);
+    }
+    config.alias = alias.to_string();
+    cmdblock_writeconfig(comm, &config, backup_file)?;
+
+    let config = cmdblock_readconfig(comm)?;
+    if config.alias != alias {
+        return Err(Error::VerifyFailed(format!(
+            "alias is <{}> instead of <{alias}>",
+            config.alias
+        )));
+    }
+    Ok(config)
+}
+
+fn cmdblock_read_sector(comm: &mut CommBulk, pos: u32) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(SECTOR_SIZE as usize);
+    for page_pos in (pos..pos + SECTOR_SIZE).step_by(PAGE_SIZE as usize) {
+        result.extend(cmd_read(comm, page_pos, PAGE_SIZE)?);
+    }
+    Ok(result)
+}
+
+fn wait_while_busy(comm: &mut CommBulk) -> Result<()> {
+    for _ in 0..500 {
+        if cmd_read_status(comm)? & 0x01 == 0 {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Err(Error::FlashBusy(
+        "status bit 0x01 still set 5s after writing".to_string(),
+    ))
+}
+
+/// Memory blocks with their offset, in the order they were read
+type Blocks = Vec<(u32, Vec<u8>)>;
+
+/// Returns the end offset, whether the block at `id_offset` is empty, and all blocks read on the way
+fn cmdblock_find_end_offset(comm: &mut CommBulk, id_offset: u32) -> Result<(u32, bool, Blocks)> {
     let mut end_offset = id_offset;
     let mut all_begin_empty = true;
+    let mut blocks = Vec::new();
     {
         let mut r1 = false;
         let mut r0 = false;
         let mut i = 0;
         while i < 2 || r0 || r1 {
             r1 = r0;
-            r0 = cmdblock_read_doublet(comm, id_offset + i * 0x1000, &mut None); // TODO maybe also datadump here. we don't want to lose anything, be I also know we read these blocks multiple times
+            let doublet = cmdblock_read_doublet(comm, id_offset + i * 0x1000)?;
+            r0 = doublet.len() > 1;
+            blocks.extend(doublet);
             if r0 {
                 end_offset = id_offset + i * 0x1000;
                 all_begin_empty = false;
@@ -163,53 +525,300 @@ fn cmdblock_find_end_offset(comm: &mut CommBulk, id_offset: u32) -> (u32, bool)
             i += 1;
         }
 
-        cmd_read(comm, id_offset + (i - 1) * 0x1000 + 0xf80, 0x080); // from data dump of original software. no clue
+        let pos = id_offset + (i - 1) * 0x1000 + 0xf80;
+        blocks.push((pos, cmd_read(comm, pos, 0x080)?)); // from data dump of original software. no clue
     }
-    (end_offset, all_begin_empty)
+    Ok((end_offset, all_begin_empty, blocks))
 }
 
-fn cmdblock_identify(
+/**
+ Start of every session: returns model, offset of the first unused memory block and identification
+*/
+pub fn cmdblock_identify(
     comm: &mut CommBulk,
     conf_orig_sw_meta: bool,
-) -> (Model, u32, IdentificationJson) {
+) -> Result<(Model, u32, IdentificationJson)> {
     debug!("cmdblock_identify()");
 
     // NmeaSwitchCommand enable=1
-    cmd_nmea_switch(comm, true);
+    cmd_nmea_switch(comm, true)?;
 
     // ModelCommand
-    let model = cmd_model(comm);
-    println!("Model: {model}");
+    let model = cmd_model(comm)?;
+    info!("Model: {model}");
 
     // IdentificationCommand
-    let id_struct = cmd_identification(comm, conf_orig_sw_meta);
+    let id_struct = cmd_identification(comm, conf_orig_sw_meta)?;
 
     // CountCommand
-    let offset = cmd_count(comm);
+    let offset = cmd_count(comm)?;
+
+    Ok((model, offset, id_struct))
+}
 
-    (model, offset, id_struct)
+/**
+ Whether the first record at [`FIRST_DATA_OFFSET`] was exported already, i.e. isn't newer than `last_waypoint_time`.
+ If the device was cleared by another program since the last download, new records are written there
+*/
+fn cmdblock_first_record_downloaded(
+    comm: &mut CommBulk,
+    last_waypoint_time: Option<DateTime<Utc>>,
+) -> Result<bool> {
+    let chunk = cmd_read(comm, FIRST_DATA_OFFSET, CHUNK_SIZE as u16)?;
+    let first_time = first_record_time(FIRST_DATA_OFFSET, &chunk);
+    debug!("first record: {first_time:?}, last downloaded: {last_waypoint_time:?}");
+    Ok(matches!((first_time, last_waypoint_time), (Some(first), Some(last)) if first <= last))
 }
 
 /*
  * Seen in original software: Read 0x100 bytes first, and then more if they were not all == 0xFF
+ * Returns the blocks read with their offset, only the first one if it was empty
  */
-fn cmdblock_read_doublet(
-    comm: &mut CommBulk,
-    pos: u32,
-    datadumper_ref: &mut Option<&mut Gt120bDataDump>,
-) -> bool {
-    let resp1 = cmd_read(comm, pos, 0x0100); // beginning. also used for probing
+fn cmdblock_read_doublet(comm: &mut CommBulk, pos: u32) -> Result<Blocks> {
+    let resp1 = cmd_read(comm, pos, 0x0100)?; // beginning. also used for probing
     if resp1 == vec![0xff; 0x0100] {
         trace!("empty block. skip 2nd read");
-        return false;
+        return Ok(vec![(pos, resp1)]);
     }
 
-    if let Some(datadumper) = datadumper_ref {
-        datadumper.process_datablock(resp1);
+    let resp2 = cmd_read(comm, pos + 0x000100, 0x0f00)?; // rest
+    Ok(vec![(pos, resp1), (pos + 0x000100, resp2)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::utc_dt_from_ymd_hms_milli;
+    use crate::intf_file::IntfFile;
+
+    fn replay(file_name: &str) -> CommBulk {
+        let path = format!("{}/testdata/{file_name}", env!("CARGO_MANIFEST_DIR"));
+        CommBulk::new(Box::new(IntfFile::new(path).unwrap()))
     }
-    let resp2 = cmd_read(comm, pos + 0x000100, 0x0f00); // rest
-    if let Some(datadumper) = datadumper_ref {
-        datadumper.process_datablock(resp2);
+
+    fn backup_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("igotu-backup-{name}-{}.bin", std::process::id()))
+    }
+
+    #[test]
+    fn cmdblock_writeconfig_replay() {
+        let mut comm = replay("config-write.txt");
+        let sector = cmdblock_read_sector(&mut replay("config-write.txt"), CONFIG_POS).unwrap();
+        let mut config = DeviceConfig::from_bytes(&sector[..CONFIG_SIZE as usize]).unwrap();
+        config.log_interval_s = 10;
+        config.alias = "Rider 1".to_string();
+
+        let backup_file = backup_path("writeconfig");
+
+        cmdblock_writeconfig(&mut comm, &config, &backup_file).unwrap();
+
+        let backup = std::fs::read(&backup_file).unwrap();
+        std::fs::remove_file(&backup_file).unwrap();
+        assert_eq!(backup, sector);
+    }
+
+    #[test]
+    fn cmdblock_writeconfig_replay_other_bytes() {
+        let mut comm = replay("config-write.txt");
+        let sector = cmdblock_read_sector(&mut replay("config-write.txt"), CONFIG_POS).unwrap();
+        let mut config = DeviceConfig::from_bytes(&sector[..CONFIG_SIZE as usize]).unwrap();
+        config.log_interval_s = 11;
+        config.alias = "Rider 1".to_string();
+
+        let backup_file = backup_path("writeconfig-other");
+
+        let result = cmdblock_writeconfig(&mut comm, &config, &backup_file);
+
+        // the backup was written before the erase, so it is there although the write failed
+        let backup = std::fs::read(&backup_file).unwrap();
+        std::fs::remove_file(&backup_file).unwrap();
+        assert!(matches!(result, Err(Error::SimulatorMismatch(_))));
+        assert_eq!(backup, sector);
+    }
+
+    /// Runs a replay with the state file, returns the names of the files written to `dir`, sorted
+    fn incremental_run(file_name: &str, state_file: &Path, dir: &Path) -> Vec<String> {
+        let conf = WorkflowConfig {
+            state_file: Some(state_file.to_string_lossy().to_string()),
+            output_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        // the replays read only the expected blocks, any other read fails
+        workflow(&mut replay(file_name), &conf).unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// The state of the only device in the state file
+    fn saved_state(state_file: &Path) -> DeviceDownloadState {
+        let content = std::fs::read_to_string(state_file).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let devices = json["devices"].as_object().unwrap();
+        assert_eq!(devices.len(), 1);
+        serde_json::from_value(devices.values().next().unwrap().clone()).unwrap()
+    }
+
+    fn incremental_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("igotu-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn workflow_incremental_first_run() {
+        let dir = incremental_dir("incremental-first");
+        let state_file = dir.join("state.json");
+
+        let names = incremental_run("incremental-1.txt", &state_file, &dir.join("1"));
+        let state = saved_state(&state_file);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert_eq!(state.next_offset, 0x2000);
+        assert_eq!(
+            state.last_waypoint_time,
+            Some(utc_dt_from_ymd_hms_milli(2025, 8, 2, 10, 0, 3, 0))
+        );
+    }
+
+    #[test]
+    fn workflow_incremental_second_run() {
+        let dir = incremental_dir("incremental-second");
+        let state_file = dir.join("state.json");
+
+        let first = incremental_run("incremental-1.txt", &state_file, &dir.join("1"));
+        // reads from 0x2000 only, track B in that block is not written again
+        let second = incremental_run("incremental-2.txt", &state_file, &dir.join("2"));
+        let state = saved_state(&state_file);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(second.len(), 2, "{second:?}");
+        assert!(
+            second.iter().all(|name| !first.contains(name)),
+            "{first:?} {second:?}"
+        );
+        assert!(second[0].contains("2025-08-03"), "{second:?}");
+        assert!(second[1].contains("2025-08-04"), "{second:?}");
+        assert_eq!(state.next_offset, 0x3000);
+        assert_eq!(
+            state.last_waypoint_time,
+            Some(utc_dt_from_ymd_hms_milli(2025, 8, 4, 10, 0, 3, 0))
+        );
+    }
+
+    #[test]
+    fn workflow_incremental_memory_cleared() {
+        let dir = incremental_dir("incremental-cleared");
+        let state_file = dir.join("state.json");
+
+        incremental_run("incremental-1.txt", &state_file, &dir.join("1"));
+        incremental_run("incremental-2.txt", &state_file, &dir.join("2"));
+        // cleared by another program and written again up to the old next_offset: reads from 0x1000
+        let names = incremental_run("incremental-cleared.txt", &state_file, &dir.join("3"));
+        let state = saved_state(&state_file);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names.len(), 3, "{names:?}");
+        assert!(names[0].contains("2025-08-10"), "{names:?}");
+        assert_eq!(state.next_offset, 0x3000);
+        assert_eq!(
+            state.last_waypoint_time,
+            Some(utc_dt_from_ymd_hms_milli(2025, 8, 12, 10, 0, 3, 0))
+        );
+    }
+
+    #[test]
+    fn workflow_tolerate_unknown_state_replay() {
+        let dir = std::env::temp_dir().join(format!("igotu-unknown-state-{}", std::process::id()));
+        let conf = WorkflowConfig {
+            tolerate_unknown_state: true,
+            output_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+
+        // the replay has no time setting, it would fail on it
+        workflow(&mut replay("download-unknown-state.txt"), &conf).unwrap();
+
+        let files: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(content.contains("11223f4455667788"), "{content}");
+    }
+
+    #[test]
+    fn workflow_unknown_state_not_cleared() {
+        let dir = std::env::temp_dir().join(format!("igotu-unknown-clear-{}", std::process::id()));
+        let conf = WorkflowConfig {
+            tolerate_unknown_state: true,
+            clear: true,
+            output_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+
+        // the replay has no delete command, it would fail on it
+        let result = workflow(&mut replay("download-unknown-state.txt"), &conf);
+        let written = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(result, Err(Error::UnknownDeviceState(_))));
+        assert_eq!(written, 1);
+
+        let result = workflow(
+            &mut replay("download-unknown-state.txt"),
+            &WorkflowConfig::default(),
+        );
+        assert!(matches!(result, Err(Error::UnknownDeviceState(_))));
+    }
+
+    #[test]
+    fn cmdblock_set_alias_replay() {
+        let mut comm = replay("set-alias.txt");
+
+        let backup_file = backup_path("set-alias");
+
+        let config = cmdblock_set_alias(&mut comm, "Rider 1", &backup_file).unwrap();
+
+        std::fs::remove_file(&backup_file).unwrap();
+        assert_eq!(config.alias, "Rider 1");
+        assert_eq!(config.log_interval_s, 5);
+    }
+
+    #[test]
+    fn cmdblock_set_alias_already_set() {
+        // the replay has only the config read, any write would fail on it
+        let mut comm = replay("set-alias-unchanged.txt");
+
+        let backup_file = backup_path("set-alias-unchanged");
+
+        let config = cmdblock_set_alias(&mut comm, "GT120B-0D66", &backup_file).unwrap();
+
+        assert_eq!(config.alias, "GT120B-0D66");
+        assert!(!backup_file.exists());
+    }
+
+    #[test]
+    fn cmdblock_set_alias_too_long() {
+        let mut comm = replay("config-write.txt");
+
+        let result = cmdblock_set_alias(
+            &mut comm,
+            "this alias is longer than 32 bytes",
+            &backup_path("set-alias-too-long"),
+        );
+
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
     }
-    true
 }
@@ -1,6 +1,8 @@
+use crate::error::Result;
+
 pub trait Intf {
-    fn send_and_receive(&mut self, to_device: Vec<u8>) -> Vec<u8>;
-    fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>);
+    fn send_and_receive(&mut self, to_device: Vec<u8>) -> Result<Vec<u8>>;
+    fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) -> Result<()>;
 
-    fn get_time_micros(&self) -> u64;
+    fn get_time_micros(&self) -> Result<u64>;
 }
@@ -1,4 +1,4 @@
-use log::{info, trace};
+use log::{debug, info, trace};
 use nusb::io::EndpointRead;
 use nusb::transfer::{Bulk, ControlOut, ControlType, In, Out, Recipient};
 use nusb::{Device, DeviceInfo, Interface, MaybeFuture};
@@ -8,6 +8,7 @@ use std::time::Duration;
 use std::time::SystemTime;
 use std::{thread, time};
 
+use crate::error::{Error, Result};
 use crate::intf;
 pub use intf::Intf;
 
@@ -16,6 +17,7 @@ const DEVID_PRODUCT: u16 = 0x0920;
 const DEVICE_INTERFACE: u8 = 1;
 const BULK_EP_IN: u8 = 0x81;
 const BULK_EP_OUT: u8 = 0x01;
+const USB_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct IntfBulk {
     device: Device,
@@ -25,82 +27,83 @@ pub struct IntfBulk {
 }
 
 impl Intf for IntfBulk {
-    fn send_and_receive(&mut self, to_device: Vec<u8>) -> Vec<u8> {
+    fn send_and_receive(&mut self, to_device: Vec<u8>) -> Result<Vec<u8>> {
         let mut reader = self
             .interface
-            .endpoint::<Bulk, In>(BULK_EP_IN)
-            .unwrap()
-            .reader(4096);
+            .endpoint::<Bulk, In>(BULK_EP_IN)?
+            .reader(4096)
+            .with_read_timeout(USB_TIMEOUT);
         let mut writer = self
             .interface
-            .endpoint::<Bulk, Out>(BULK_EP_OUT)
-            .unwrap()
-            .writer(4096);
+            .endpoint::<Bulk, Out>(BULK_EP_OUT)?
+            .writer(4096)
+            .with_write_timeout(USB_TIMEOUT);
 
-        writer.write_all(&to_device).unwrap();
-        writer.flush_end().unwrap();
+        writer.write_all(&to_device).map_err(usb_io_error)?;
+        writer.flush_end().map_err(usb_io_error)?;
 
         trace!("  awaiting answer");
-        let mut answer = self.read_answer(&mut reader);
+        let mut answer = self.read_answer(&mut reader)?;
 
         let payloadsize = u16::from_be_bytes(answer[1..3].try_into().unwrap());
         while answer.len() < payloadsize as usize + 4 {
             trace!("  waiting for more data");
-            answer.append(&mut self.read_answer(&mut reader));
+            answer.append(&mut self.read_answer(&mut reader)?);
         }
 
-        answer
+        Ok(answer)
     }
 
-    fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) {
+    fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) -> Result<()> {
         let mut writer = self
             .interface
-            .endpoint::<Bulk, Out>(BULK_EP_OUT)
-            .unwrap()
-            .writer(4096);
-        writer.write_all(&to_device).unwrap();
-        writer.flush().unwrap();
+            .endpoint::<Bulk, Out>(BULK_EP_OUT)?
+            .writer(4096)
+            .with_write_timeout(USB_TIMEOUT);
+        writer.write_all(&to_device).map_err(usb_io_error)?;
+        writer.flush().map_err(usb_io_error)?;
 
         info!("Wait for device reset. It will shortly disconnect from USB");
         let (device, device_info, interface) =
-            Self::setup_device_and_interface(true, self.bus_id, self.device_id);
+            Self::setup_device_and_interface(true, self.bus_id, self.device_id)?;
         self.device = device;
         self.interface = interface;
         self.bus_id = device_info.busnum();
         self.device_id = device_info.device_address();
+        Ok(())
     }
 
-    fn get_time_micros(&self) -> u64 {
+    fn get_time_micros(&self) -> Result<u64> {
         let duration_since_epoch = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
         let timestamp_micros = duration_since_epoch.as_micros();
-        timestamp_micros as u64
+        Ok(timestamp_micros as u64)
     }
 }
 
 impl IntfBulk {
-    pub fn new() -> Self {
-        let (device, device_info, interface) = Self::setup_device_and_interface(false, 0xff, 0xff);
-        Self {
+    pub fn new() -> Result<Self> {
+        let (device, device_info, interface) = Self::setup_device_and_interface(false, 0xff, 0xff)?;
+        Ok(Self {
             device,
             interface,
             bus_id: device_info.busnum(),
             device_id: device_info.device_address(),
-        }
+        })
     }
 
-    fn wait_for_deviceinfo(wait: bool, bus_id: u8, device_id: u8) -> DeviceInfo {
+    fn wait_for_deviceinfo(wait: bool, bus_id: u8, device_id: u8) -> Result<DeviceInfo> {
         let mut sleep_time = 1000;
         loop {
-            let di_opt = nusb::list_devices().wait().unwrap().find(|d| {
+            let di_opt = nusb::list_devices().wait()?.find(|d| {
                 d.vendor_id() == DEVID_VENDOR
                     && d.product_id() == DEVID_PRODUCT
                     && match_partially_last_device(d, bus_id, device_id)
             });
 
             if let Some(di) = di_opt {
-                return di;
+                return Ok(di);
             }
             if wait {
                 thread::sleep(time::Duration::from_millis(sleep_time));
@@ -111,7 +114,7 @@ impl IntfBulk {
                     sleep_time = sleep_time * 3 / 2;
                 }
             } else {
-                panic!("Cannot find device");
+                return Err(Error::DeviceNotFound);
             }
         }
     }
@@ -120,37 +123,34 @@ impl IntfBulk {
         wait: bool,
         bus_id: u8,
         device_id: u8,
-    ) -> (Device, DeviceInfo, Interface) {
-        let di = Self::wait_for_deviceinfo(wait, bus_id, device_id);
+    ) -> Result<(Device, DeviceInfo, Interface)> {
+        let di = Self::wait_for_deviceinfo(wait, bus_id, device_id)?;
 
         info!("USB Device info: {di:?}");
 
-        let mut device = di.open().wait().unwrap();
-        let interface = device
-            .detach_and_claim_interface(DEVICE_INTERFACE)
-            .wait()
-            .unwrap();
+        let mut device = di.open().wait()?;
+        let interface = device.detach_and_claim_interface(DEVICE_INTERFACE).wait()?;
 
-        Self::ctrl_set_line_state(&mut device);
-        (device, di, interface)
+        Self::ctrl_set_line_state(&mut device)?;
+        Ok((device, di, interface))
     }
 
-    fn read_answer(&mut self, reader: &mut EndpointRead<Bulk>) -> Vec<u8> {
+    fn read_answer(&mut self, reader: &mut EndpointRead<Bulk>) -> Result<Vec<u8>> {
         let mut result = vec![0; 4];
-        reader.read_exact(&mut result).unwrap();
+        reader.read_exact(&mut result).map_err(usb_io_error)?;
         let payloadsize = u16::from_be_bytes(result[1..3].try_into().unwrap());
 
         let mut buf = vec![0; payloadsize.into()];
-        reader.read_exact(&mut buf).unwrap();
+        reader.read_exact(&mut buf).map_err(usb_io_error)?;
         result.extend_from_slice(&buf);
-        result
+        Ok(result)
     }
 
     /**
      set control line state request - needed for the device to reply in BULK mode
     */
-    fn ctrl_set_line_state(device: &mut Device) {
-        println!("Send ctrl_set_line_state");
+    fn ctrl_set_line_state(device: &mut Device) -> Result<()> {
+        debug!("Send ctrl_set_line_state");
         device
             .control_out(
                 ControlOut {
@@ -164,7 +164,7 @@ impl IntfBulk {
                 Duration::from_secs(3),
             )
             .wait()
-            .unwrap();
+            .map_err(|err| usb_io_error(err.into()))
     }
 }
 
@@ -178,3 +178,13 @@ fn match_partially_last_device(devicepath: &DeviceInfo, bus_id: u8, device_id: u
     }
     devicepath.busnum() == bus_id && devicepath.device_address() != device_id
 }
+
+fn usb_io_error(err: std::io::Error) -> Error {
+    match err.kind() {
+        std::io::ErrorKind::TimedOut => Error::UsbTimeout(err.to_string()),
+        std::io::ErrorKind::NotConnected | std::io::ErrorKind::ConnectionAborted => {
+            Error::UsbDisconnected(err.to_string())
+        }
+        _ => Error::Usb(err.to_string()),
+    }
+}
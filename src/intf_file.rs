@@ -3,6 +3,7 @@ extern crate chrono;
 use chrono::{TimeZone, Utc};
 use std::fs::read_to_string;
 
+use crate::error::{Error, Result};
 use crate::intf;
 pub use intf::Intf;
 
@@ -19,13 +20,13 @@ struct InOut {
 }
 
 impl IntfFile {
-    pub fn new(file_name: String) -> Self {
+    pub fn new(file_name: String) -> Result<Self> {
         info!("\n\nRUNNING SIMULATOR with file {}\n\n", file_name);
 
         let mut result = Vec::new();
 
         let mut next_comment: String = "".to_string();
-        for (line_num, line) in read_to_string(file_name).unwrap().lines().enumerate() {
+        for (line_num, line) in read_to_string(file_name)?.lines().enumerate() {
             let next_isout;
             if line.is_empty() || line.starts_with("#") {
                 if line.starts_with("#: ") {
@@ -42,52 +43,68 @@ impl IntfFile {
             }
             result.push(InOut {
                 out: next_isout,
-                line: hex::decode(line.to_string()[2..].replace(":", "")).expect("Decoding failed"),
+                line: hex::decode(line.to_string()[2..].replace(":", "")).map_err(|err| {
+                    Error::SimulatorMismatch(format!("#{line_num}: Decoding failed: {err}"))
+                })?,
                 line_num,
                 comment: next_comment,
             });
             next_comment = "".to_string();
         }
-        Self {
+        Ok(Self {
             lines: result,
             next_line: 0,
-        }
+        })
     }
 
-    fn sim_send(&mut self, to_device: Vec<u8>) -> &InOut {
-        let out_line = &self.lines[self.next_line];
+    fn next_inout(&mut self) -> Result<&InOut> {
+        let Some(line) = self.lines.get(self.next_line) else {
+            return Err(Error::SimulatorMismatch(
+                "Replay file ended, but the code continues".to_string(),
+            ));
+        };
         self.next_line += 1;
+        Ok(line)
+    }
+
+    fn sim_send(&mut self, to_device: Vec<u8>) -> Result<&InOut> {
+        let out_line = self.next_inout()?;
         if !out_line.comment.is_empty() {
             debug!("SIMULATOR >#{}: {}", out_line.line_num, out_line.comment);
         } else {
             debug!("SIMULATOR >#{}", out_line.line_num);
         }
         if !out_line.out {
-            panic!("SIMULATOR >#{}: No cmd-line", out_line.line_num);
+            return Err(Error::SimulatorMismatch(format!(
+                ">#{}: No cmd-line",
+                out_line.line_num
+            )));
         }
         if out_line.line != to_device {
-            panic!(
-                "SIMULATOR >#{}: Next cmd doesn't match:\ncode:   {:02X?}\nscript: {:02X?}",
+            return Err(Error::SimulatorMismatch(format!(
+                ">#{}: Next cmd doesn't match:\ncode:   {:02X?}\nscript: {:02X?}",
                 out_line.line_num, to_device, out_line.line
-            );
+            )));
         }
-        out_line
+        Ok(out_line)
     }
 }
 
 impl Intf for IntfFile {
-    fn send_and_receive(&mut self, to_device: Vec<u8>) -> Vec<u8> {
-        self.sim_send(to_device);
+    fn send_and_receive(&mut self, to_device: Vec<u8>) -> Result<Vec<u8>> {
+        self.sim_send(to_device)?;
 
-        let in_line = &self.lines[self.next_line];
-        self.next_line += 1;
+        let in_line = self.next_inout()?;
         if !in_line.comment.is_empty() {
             debug!("SIMULATOR <#{}: {}", in_line.line_num, in_line.comment);
         } else {
             debug!("SIMULATOR <#{}", in_line.line_num);
         }
         if in_line.out {
-            panic!("SIMULATOR <#{}: Not a response in line", in_line.line_num);
+            return Err(Error::SimulatorMismatch(format!(
+                "<#{}: Not a response in line",
+                in_line.line_num
+            )));
         }
         let mut line = in_line.line.clone();
         while self.next_line < self.lines.len() && !self.lines[self.next_line].out {
@@ -98,26 +115,28 @@ impl Intf for IntfFile {
             }
             line.append(&mut in_line.line.to_vec());
         }
-        line
+        Ok(line)
     }
 
-    fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) {
-        let out_line = self.sim_send(to_device);
+    fn cmd_oneway_devicereset(&mut self, to_device: Vec<u8>) -> Result<()> {
+        let out_line = self.sim_send(to_device)?;
         debug!("SIMULATOR #{}: Device reset now", out_line.line_num);
+        Ok(())
     }
 
-    fn get_time_micros(&self) -> u64 {
-        let comment_line = &self.lines[self.next_line].comment;
-        let i0 = comment_line.find("us=").unwrap();
+    fn get_time_micros(&self) -> Result<u64> {
+        let no_time = || Error::SimulatorMismatch("No time (us=...) in next comment".to_string());
+        let comment_line = &self.lines.get(self.next_line).ok_or_else(no_time)?.comment;
+        let i0 = comment_line.find("us=").ok_or_else(no_time)?;
         let line2 = comment_line[(i0 + 3)..].to_string();
-        let i1 = line2.find(")").unwrap();
-        let time_us = line2[0..i1].parse::<u64>().unwrap();
+        let i1 = line2.find(")").ok_or_else(no_time)?;
+        let time_us = line2[0..i1].parse::<u64>().map_err(|_| no_time())?;
         let dt = Utc.timestamp_micros(time_us.try_into().unwrap());
         debug!(
             "SIMULATOR: dummy time: us={}, {}",
             time_us,
             dt.single().unwrap().to_rfc2822()
         );
-        time_us
+        Ok(time_us)
     }
 }
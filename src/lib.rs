@@ -0,0 +1,44 @@
+/*!
+ User-space driver for the MobileAction i-gotU GT-120B GPS data logger.
+
+ The entry point is [`Device`], which connects to the logger via USB (or a replay file) and offers the device
+ commands. [`Device::download`] runs the complete download workflow like the command line tool does.
+ For more control, the commands can be combined freely and the memory blocks fed into a [`Gt120bDataDump`],
+ which decodes them into [`DatablockEnum`] records and exports them.
+*/
+pub mod comm_bulk;
+pub mod commands;
+pub mod device;
+pub mod device_config;
+pub mod download_state;
+pub mod error;
+pub mod export_csv;
+pub mod export_fit;
+pub mod export_geojson;
+pub mod export_gpx;
+pub mod export_kml;
+pub mod export_nmea;
+pub mod export_tcx;
+pub mod export_verify;
+pub mod file_name;
+pub mod gt120b_datadump;
+pub mod gt120b_workflow;
+pub mod intf;
+pub mod intf_bulk;
+pub mod intf_file;
+pub mod probe_state;
+pub mod quality_filter;
+pub mod raw_dump;
+pub mod timezone;
+pub mod track_stats;
+mod xml;
+
+pub use crate::commands::{IdentificationJson, Model};
+pub use crate::device::Device;
+pub use crate::device_config::DeviceConfig;
+pub use crate::error::{Error, Result};
+pub use crate::gt120b_datadump::{
+    ButtonEnum, DatablockEnum, ExportFormat, ExportedFile, Gt120bDataDump, SplitPolicy, Track,
+    Waypoint,
+};
+pub use crate::gt120b_workflow::WorkflowConfig;
@@ -1,18 +1,18 @@
+use clap::{ArgAction, Parser, Subcommand};
 use env_logger::Builder;
 use env_logger::Env;
-mod comm_bulk;
-mod commands;
-mod gt120b_datadump;
-mod gt120b_workflow;
-mod intf;
-mod intf_bulk;
-mod intf_file;
-use crate::comm_bulk::CommBulk;
-use crate::gt120b_workflow::workflow;
-use crate::intf::Intf;
-use crate::intf_bulk::IntfBulk;
-use crate::intf_file::IntfFile;
-use clap::Parser;
+use igotu_gt120b::export_gpx::ButtonWaypoints;
+use igotu_gt120b::file_name::{CollisionPolicy, FileNameTemplate};
+use igotu_gt120b::gt120b_datadump::{ChunkHeader, RECORD_SIZE};
+use igotu_gt120b::gt120b_workflow::workflow_parse_raw;
+use igotu_gt120b::quality_filter::QualityFilter;
+use igotu_gt120b::raw_dump::RawDump;
+use igotu_gt120b::timezone::Timezone;
+use igotu_gt120b::{
+    Device, DeviceConfig, Error, ExportFormat, Result, SplitPolicy, WorkflowConfig,
+};
+use log::error;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -25,21 +25,188 @@ struct Args {
     #[arg(long, default_value_t = false)]
     orig_sw_workflow: bool,
 
-    /// Use the exact same meta format as the original software. By default, the format is more verbose and not base64 encoded
-    #[arg(long, default_value_t = true)]
+    /// Use the exact same meta format as the original software (default). With `--orig-sw-meta false`, the format is more
+    /// verbose, not base64 encoded, and contains an unknown device state
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
     orig_sw_meta: bool,
 
+    /// Write gpx files byte-compatible to the original software (literal BOM, numbers as stored)
+    #[arg(long, default_value_t = false)]
+    orig_sw_gpx: bool,
+
     /// Simulate using specified replay file instead of real hardware access
-    #[arg(long)]
+    #[arg(long, global = true)]
     sim_file_name: Option<String>,
 
     /// filename part on the left side of the date, including optional path
-    #[arg(short, long, default_value = "")]
+    #[arg(short, long, default_value = "", conflicts_with = "name_template")]
     prefix: String,
 
     /// filename part on the right side of the date
-    #[arg(short, long, default_value = "")]
+    #[arg(short, long, default_value = "", conflicts_with = "name_template")]
     suffix: String,
+
+    /// Directory for the output files, created if missing
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// File name template like {alias}/{start:%Y}/{start}_{device_id}.{ext}. Placeholders: alias, serial, device_id, index, start, end (with optional chrono format), distance (km), ext
+    #[arg(long)]
+    name_template: Option<FileNameTemplate>,
+
+    /// If an output file exists: overwrite, skip or suffix (append _1, _2, ...)
+    #[arg(long, default_value = "overwrite")]
+    on_collision: CollisionPolicy,
+
+    /// Incremental download: remember what was downloaded in this file and only export new data next time
+    #[arg(long)]
+    state_file: Option<String>,
+
+    /// Also write a bit-exact copy of all memory blocks read to this file, for archiving or `parse-raw`
+    #[arg(long)]
+    dump_raw: Option<String>,
+
+    /// Also write the track statistics (distance, times, speeds, elevation, ...) as JSON to this file
+    #[arg(long)]
+    stats_json: Option<String>,
+
+    /// Output file format: gpx, kml, kmz, geojson, csv, tcx, fit or nmea
+    #[arg(long, default_value = "gpx")]
+    format: ExportFormat,
+
+    /// Files to write: track (one per track, split at day change), single, day or hours dividing 24 like 6h. Default: day for tcx and fit, track for the others. Ignored for csv
+    #[arg(long)]
+    split: Option<SplitPolicy>,
+
+    /// Don't split a track at the local day change if it started less than this many hours before
+    #[arg(long)]
+    day_change_grace: Option<u32>,
+
+    /// Write button presses as waypoints (<wpt>) into gpx files, located at the nearest track point
+    #[arg(long, default_value_t = false)]
+    button_waypoints: bool,
+
+    /// Symbol of the button press waypoints
+    #[arg(long, default_value = "Flag, Blue")]
+    waypoint_sym: String,
+
+    /// Write the button press waypoints into a separate file *_poi.gpx, with or without --button-waypoints
+    #[arg(long, default_value_t = false)]
+    poi_file: bool,
+
+    /// End each track with the position stored when the logger was switched off (ignored by the original software)
+    #[arg(long, default_value_t = false)]
+    switch_off_points: bool,
+
+    /// Drop track points with a higher HDOP
+    #[arg(long)]
+    max_hdop: Option<f32>,
+
+    /// Drop track points with fewer satellites used
+    #[arg(long)]
+    min_sats: Option<u8>,
+
+    /// Drop track points reached faster than this (km/h) from the previous point
+    #[arg(long)]
+    max_speed: Option<f64>,
+
+    /// Drop track points further than this (meters) from the previous point
+    #[arg(long)]
+    max_jump: Option<f64>,
+
+    /// Drop track points below this elevation (meters)
+    #[arg(long, allow_hyphen_values = true)]
+    min_ele: Option<f64>,
+
+    /// Drop track points above this elevation (meters)
+    #[arg(long)]
+    max_ele: Option<f64>,
+
+    /// Timezone for file names and day splitting: IANA name like Europe/Zurich, local, or auto (from the first position). Default: file names in UTC, day splitting local
+    #[arg(long)]
+    timezone: Option<Timezone>,
+
+    /// Download read-only (don't set the time, don't clear) if the device state at 0x1fff80 is unknown, instead of stopping
+    #[arg(long, default_value_t = false)]
+    tolerate_unknown_state: bool,
+
+    /// Stop with an error if any record can't be decoded, instead of skipping it with a warning
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Without a command, tracks are downloaded
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Device configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Change the name of the device (max. 32 bytes utf-8). EXPERIMENTAL, see `config set`
+    SetAlias {
+        name: String,
+
+        /// Needed for writing, as long as the write protocol is not verified
+        #[arg(long, default_value_t = false)]
+        experimental_write: bool,
+
+        /// Where the config sector is saved before it is erased. Default: igotu-config-backup-<UTC time>.bin
+        #[arg(long)]
+        backup_file: Option<PathBuf>,
+    },
+    /// Write gpx files from a file written with --dump-raw. No device access
+    ParseRaw { file_name: String },
+    /// Print the chunk headers of a file written with --dump-raw and check them against their records
+    Chunks {
+        file_name: String,
+        /// Also print erased chunks
+        #[arg(long, default_value_t = false)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the device configuration
+    Show {
+        /// Print as JSON instead of human readable text
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Change the device configuration. EXPERIMENTAL: the write protocol is not verified with recordings of the original software yet
+    Set {
+        /// Name of the device
+        #[arg(long)]
+        alias: Option<String>,
+
+        /// Logging interval in seconds
+        #[arg(long)]
+        log_interval: Option<u8>,
+
+        /// Smart tracking: speed threshold in km/h
+        #[arg(long)]
+        smart_tracking_speed: Option<u8>,
+
+        /// Smart tracking: logging interval in seconds above the speed threshold
+        #[arg(long)]
+        smart_tracking_interval: Option<u8>,
+
+        /// Only print the changes, don't write anything to the device
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+
+        /// Needed for writing, as long as the write protocol is not verified
+        #[arg(long, default_value_t = false)]
+        experimental_write: bool,
+
+        /// Where the config sector is saved before it is erased. Default: igotu-config-backup-<UTC time>.bin
+        #[arg(long)]
+        backup_file: Option<PathBuf>,
+    },
 }
 
 fn main() {
@@ -50,21 +217,178 @@ fn main() {
 
     //dbg!(&args);
 
-    let intf: Box<dyn Intf> = if args.sim_file_name.is_none() {
-        Box::new(IntfBulk::new())
-    } else {
-        Box::new(IntfFile::new(args.sim_file_name.unwrap()))
+    if let Err(err) = run(args) {
+        error!("{err}");
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let workflow_config = WorkflowConfig {
+        clear: args.clear,
+        orig_sw_workflow: args.orig_sw_workflow,
+        orig_sw_meta: args.orig_sw_meta,
+        orig_sw_gpx: args.orig_sw_gpx,
+        prefix: args.prefix,
+        suffix: args.suffix,
+        state_file: args.state_file,
+        raw_dump_file: args.dump_raw,
+        stats_file: args.stats_json,
+        format: args.format,
+        split: args.split,
+        day_change_grace: args
+            .day_change_grace
+            .map(|hours| chrono::Duration::hours(hours as i64)),
+        button_waypoints: (args.button_waypoints || args.poi_file).then_some(ButtonWaypoints {
+            sym: args.waypoint_sym,
+            in_tracks: args.button_waypoints,
+            poi_file: args.poi_file,
+        }),
+        switch_off_points: args.switch_off_points,
+        quality_filter: QualityFilter {
+            max_hdop: args.max_hdop,
+            min_sats: args.min_sats,
+            max_speed_kmh: args.max_speed,
+            max_jump_m: args.max_jump,
+            min_ele_m: args.min_ele,
+            max_ele_m: args.max_ele,
+        },
+        timezone: args.timezone,
+        output_dir: args.output_dir,
+        name_template: args.name_template,
+        collision: args.on_collision,
+        tolerate_unknown_state: args.tolerate_unknown_state,
+        strict: args.strict,
+    };
+
+    if let Some(Command::ParseRaw { file_name }) = &args.command {
+        let num_files = workflow_parse_raw(file_name, &workflow_config)?;
+        println!("Completed. {num_files} files written.");
+        return Ok(());
+    }
+
+    if let Some(Command::Chunks { file_name, all }) = &args.command {
+        let raw_dump = RawDump::load(file_name)?;
+        let mut num_errors = 0;
+        for (offset, chunk) in raw_dump.chunks() {
+            let header = ChunkHeader::from_bytes(chunk);
+            let records = chunk[8..]
+                .chunks(RECORD_SIZE)
+                .filter(|record| record[0] != 0xff)
+                .count();
+            let status = match header.validate(&chunk[8..]) {
+                Ok(()) => "ok".to_string(),
+                Err(err) => {
+                    num_errors += 1;
+                    err.to_string()
+                }
+            };
+            if *all || !header.is_erased() || status != "ok" {
+                println!("0x{offset:06x}  {header}  {records} records  {status}");
+            }
+        }
+        println!("{num_errors} chunks with errors.");
+        return Ok(());
+    }
+
+    let mut device = match args.sim_file_name {
+        None => Device::open_usb()?,
+        Some(sim_file_name) => Device::open_replay(sim_file_name)?,
     };
-    let mut comm = CommBulk::new(intf);
-
-    workflow(
-        &mut comm,
-        args.clear,
-        args.orig_sw_workflow,
-        args.orig_sw_meta,
-        args.prefix,
-        args.suffix,
-    );
-
-    println!("Completed.");
+
+    match args.command {
+        None => {
+            device.download(&workflow_config)?;
+            println!("Completed.");
+        }
+        Some(Command::Config {
+            action: ConfigAction::Show { json },
+        }) => {
+            device.identify(args.orig_sw_meta)?;
+            let config = device.read_config()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&config).unwrap());
+            } else {
+                print!("{config}");
+            }
+        }
+        Some(Command::Config {
+            action:
+                ConfigAction::Set {
+                    alias,
+                    log_interval,
+                    smart_tracking_speed,
+                    smart_tracking_interval,
+                    dry_run,
+                    experimental_write,
+                    backup_file,
+                },
+        }) => {
+            device.identify(args.orig_sw_meta)?;
+            let old_config = device.read_config()?;
+            let mut config = old_config.clone();
+            if let Some(alias) = alias {
+                config.alias = alias;
+            }
+            if let Some(log_interval) = log_interval {
+                config.log_interval_s = log_interval;
+            }
+            if let Some(smart_tracking_speed) = smart_tracking_speed {
+                config.smart_tracking_speed_kmh = smart_tracking_speed;
+            }
+            if let Some(smart_tracking_interval) = smart_tracking_interval {
+                config.smart_tracking_interval_s = smart_tracking_interval;
+            }
+
+            let diff = old_config.diff(&config)?;
+            if diff.is_empty() {
+                println!("No changes.");
+                return Ok(());
+            }
+            for line in &diff {
+                println!("{line}");
+            }
+            if dry_run {
+                println!("Dry run, nothing written.");
+                return Ok(());
+            }
+            check_experimental_write(experimental_write)?;
+            device.write_config(&config, &backup_file.unwrap_or_else(default_backup_file))?;
+            println!("Completed.");
+        }
+        Some(Command::SetAlias {
+            name,
+            experimental_write,
+            backup_file,
+        }) => {
+            DeviceConfig::check_alias(&name)?;
+            check_experimental_write(experimental_write)?;
+            device.identify(args.orig_sw_meta)?;
+            let config =
+                device.set_alias(&name, &backup_file.unwrap_or_else(default_backup_file))?;
+            println!("Alias: {}", config.alias);
+        }
+        Some(Command::ParseRaw { .. } | Command::Chunks { .. }) => {
+            unreachable!("handled without device access")
+        }
+    }
+    Ok(())
+}
+
+/// Writing the config is refused without `--experimental-write`
+fn check_experimental_write(experimental_write: bool) -> Result<()> {
+    if !experimental_write {
+        return Err(Error::InvalidConfig(
+            "writing the config is experimental and not verified with a real device, confirm with --experimental-write"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn default_backup_file() -> PathBuf {
+    PathBuf::from(format!(
+        "igotu-config-backup-{}.bin",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    ))
 }
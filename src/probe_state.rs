@@ -0,0 +1,100 @@
+use std::fmt;
+
+/// Position of 8 bytes of unknown meaning, read by the original software. Expected to be all 0xff
+pub const STATE_PROBE_POS: u32 = 0x1fff80;
+pub const STATE_PROBE_SIZE: u16 = 8;
+
+/**
+ Decoded content of the 8 bytes at [`STATE_PROBE_POS`].
+
+ The original software reads them before every download. All devices seen so far had all 0xff (erased flash) there.
+ The meaning of other values is unknown, possibly a bad block list or an unfinished operation. They are kept as they
+ are, so they can be analyzed from user reports
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeState {
+    /// All 0xff, the only state known to be safe
+    Empty,
+    /// Any other content, or not 8 bytes
+    Unknown(Vec<u8>),
+}
+
+impl ProbeState {
+    pub fn decode(payload: &[u8]) -> Self {
+        if payload.len() == STATE_PROBE_SIZE as usize && payload.iter().all(|&b| b == 0xff) {
+            ProbeState::Empty
+        } else {
+            ProbeState::Unknown(payload.to_vec())
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, ProbeState::Empty)
+    }
+
+    /// The bytes as hex string, e.g. for metadata
+    pub fn hex(&self) -> String {
+        match self {
+            ProbeState::Empty => "ff".repeat(STATE_PROBE_SIZE as usize),
+            ProbeState::Unknown(raw) => hex::encode(raw),
+        }
+    }
+}
+
+impl fmt::Display for ProbeState {
+    /// Hex dump and the interpretations which seem plausible, as help for analyzing
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeState::Empty => write!(f, "empty (all 0xff)"),
+            ProbeState::Unknown(raw) => {
+                write!(f, "unknown: {raw:02x?}")?;
+                if raw.len() == STATE_PROBE_SIZE as usize {
+                    let le = |i: usize| u32::from_le_bytes(raw[i..i + 4].try_into().unwrap());
+                    let be = |i: usize| u32::from_be_bytes(raw[i..i + 4].try_into().unwrap());
+                    write!(
+                        f,
+                        ", u32 le: 0x{:08x} 0x{:08x}, u32 be: 0x{:08x} 0x{:08x}",
+                        le(0),
+                        le(4),
+                        be(0),
+                        be(4)
+                    )?;
+                    let erased = raw.iter().filter(|&&b| b == 0xff).count();
+                    write!(f, ", {erased} of 8 bytes erased")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn decode_empty() {
+        let state = ProbeState::decode(&[0xff; 8]);
+
+        assert!(state.is_empty());
+        assert_eq!(state.hex(), "ffffffffffffffff");
+    }
+
+    #[test]
+    fn decode_unknown() {
+        let state = ProbeState::decode(&hex!("00 10 00 00 ff ff ff ff"));
+
+        assert_eq!(
+            state,
+            ProbeState::Unknown(vec![0x00, 0x10, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff])
+        );
+        assert_eq!(state.hex(), "00100000ffffffff");
+        assert_eq!(
+            state.to_string(),
+            "unknown: [00, 10, 00, 00, ff, ff, ff, ff], u32 le: 0x00001000 0xffffffff, \
+             u32 be: 0x00100000 0xffffffff, 4 of 8 bytes erased"
+        );
+        assert!(!ProbeState::decode(&[0xff; 7]).is_empty());
+    }
+}
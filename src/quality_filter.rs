@@ -0,0 +1,162 @@
+use crate::gt120b_datadump::Waypoint;
+use std::fmt;
+
+/**
+ Thresholds for dropping implausible track points before the export. Every limit is optional, the default drops
+ nothing.
+
+ Speed and jump are measured from the previous point kept in the same track, so a single outlier doesn't also drop the
+ good point after it. After a gap, the reference moves on as soon as two points agree with each other, see
+ [`crate::Gt120bDataDump::set_quality_filter`]
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualityFilter {
+    pub max_hdop: Option<f32>,
+    /// Minimum number of satellites used for the fix
+    pub min_sats: Option<u8>,
+    /// Maximum speed between two points, from distance and time
+    pub max_speed_kmh: Option<f64>,
+    /// Maximum distance between two points
+    pub max_jump_m: Option<f64>,
+    pub min_ele_m: Option<f64>,
+    pub max_ele_m: Option<f64>,
+}
+
+/// Why a point was dropped, the first failing check counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DropReason {
+    Hdop,
+    Satellites,
+    Elevation,
+    Speed,
+    Jump,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            DropReason::Hdop => "HDOP too high",
+            DropReason::Satellites => "too few satellites",
+            DropReason::Elevation => "implausible elevation",
+            DropReason::Speed => "too fast from previous point",
+            DropReason::Jump => "too far from previous point",
+        };
+        write!(f, "{text}")
+    }
+}
+
+impl QualityFilter {
+    pub fn is_active(&self) -> bool {
+        *self != QualityFilter::default()
+    }
+
+    /// `prev`: the previous point kept in the same track, if any
+    pub fn check(&self, prev: Option<&Waypoint>, wpt: &Waypoint) -> Option<DropReason> {
+        if self.max_hdop.is_some_and(|max| wpt.hdop > max) {
+            return Some(DropReason::Hdop);
+        }
+        if self.min_sats.is_some_and(|min| wpt.sat_used < min) {
+            return Some(DropReason::Satellites);
+        }
+        if self.min_ele_m.is_some_and(|min| wpt.ele() < min)
+            || self.max_ele_m.is_some_and(|max| wpt.ele() > max)
+        {
+            return Some(DropReason::Elevation);
+        }
+        let prev = prev?;
+        let distance_m = prev.distance_to(wpt);
+        let secs = (wpt.time - prev.time).num_milliseconds() as f64 / 1000.0;
+        if secs > 0.0
+            && self
+                .max_speed_kmh
+                .is_some_and(|max| distance_m / secs * 3.6 > max)
+        {
+            return Some(DropReason::Speed);
+        }
+        if self.max_jump_m.is_some_and(|max| distance_m > max) {
+            return Some(DropReason::Jump);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn check_reasons() {
+        let filter = QualityFilter {
+            max_hdop: Some(10.0),
+            min_sats: Some(4),
+            max_speed_kmh: Some(200.0),
+            max_jump_m: Some(100.0),
+            min_ele_m: Some(-100.0),
+            max_ele_m: Some(5000.0),
+        };
+        let t0 = utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 0, 0);
+        let t10 = utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 10, 0);
+        let t59 = utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 59, 0);
+        let prev = Waypoint {
+            lat_e7: 473660000,
+            ..test_waypoint(t0)
+        };
+        // 11 m in 10 s
+        let next = || Waypoint {
+            lat_e7: 473661000,
+            ..test_waypoint(t10)
+        };
+
+        assert_eq!(filter.check(Some(&prev), &next()), None);
+        assert_eq!(
+            filter.check(
+                Some(&prev),
+                &Waypoint {
+                    hdop: 53.5,
+                    ..next()
+                }
+            ),
+            Some(DropReason::Hdop)
+        );
+        assert_eq!(
+            filter.check(
+                Some(&prev),
+                &Waypoint {
+                    sat_used: 3,
+                    ..next()
+                }
+            ),
+            Some(DropReason::Satellites)
+        );
+        assert_eq!(
+            filter.check(
+                Some(&prev),
+                &Waypoint {
+                    ele_cm: 900000,
+                    ..next()
+                }
+            ),
+            Some(DropReason::Elevation)
+        );
+        // 1.1 km in 10 s
+        assert_eq!(
+            filter.check(
+                Some(&prev),
+                &Waypoint {
+                    lat_e7: 473760000,
+                    ..next()
+                }
+            ),
+            Some(DropReason::Speed)
+        );
+        // 111 m in 59 s
+        let far = Waypoint {
+            lat_e7: 473670000,
+            ..test_waypoint(t59)
+        };
+        assert_eq!(filter.check(Some(&prev), &far), Some(DropReason::Jump));
+        assert_eq!(filter.check(None, &far), None);
+        assert!(!QualityFilter::default().is_active());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::commands::IdentificationJson;
+use crate::device_config::CONFIG_POS;
+use crate::error::{Error, Result};
+use crate::file_name::OutputFile;
+use crate::gt120b_datadump::CHUNK_SIZE;
+use crate::gt120b_workflow::STATE_PROBE_POS;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"IGOTURAW";
+const FORMAT_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RawDumpHeader {
+    pub identification: IdentificationJson,
+    /// Offset returned by the count command
+    pub count_offset: u32,
+    /// The bytes at [`STATE_PROBE_POS`] as hex, also stored as block. Missing in older dumps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe_state: Option<String>,
+    /// Number of blocks after config and state which were read while searching the end of the data. The same memory
+    /// is read again for the download, so they aren't parsed. Missing in older dumps
+    #[serde(default)]
+    pub search_blocks: usize,
+}
+
+/**
+ Bit-exact copy of all memory blocks read from the device, for archiving and for parsing again later.
+
+ File format (all numbers little endian):
+ - magic `IGOTURAW`, format version (u8)
+ - header length (u32), header as json
+ - number of blocks (u32), then per block: offset (u32), length (u32), data
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawDump {
+    pub header: RawDumpHeader,
+    /// Memory blocks with their offset, in the order they were read
+    pub blocks: Vec<(u32, Vec<u8>)>,
+}
+
+impl RawDump {
+    pub fn save(&self, file_name: &str) -> Result<()> {
+        info!("Writing raw dump {file_name}");
+        let mut f = OutputFile::create(Path::new(file_name))?;
+        let header = serde_json::to_vec(&self.header).unwrap();
+        f.write_all(MAGIC)?;
+        f.write_all(&[FORMAT_VERSION])?;
+        f.write_all(&(header.len() as u32).to_le_bytes())?;
+        f.write_all(&header)?;
+        f.write_all(&(self.blocks.len() as u32).to_le_bytes())?;
+        for (offset, data) in &self.blocks {
+            f.write_all(&offset.to_le_bytes())?;
+            f.write_all(&(data.len() as u32).to_le_bytes())?;
+            f.write_all(data)?;
+        }
+        f.commit()?;
+        Ok(())
+    }
+
+    pub fn load(file_name: &str) -> Result<Self> {
+        info!("Reading raw dump {file_name}");
+        let file = File::open(file_name)?;
+        let mut remaining = file.metadata()?.len();
+        let mut f = BufReader::new(file);
+        let invalid = |msg: &str| Error::InvalidRawDump(format!("{file_name}: {msg}"));
+
+        let mut magic = [0u8; 8];
+        f.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a raw dump file"));
+        }
+        remaining = remaining.saturating_sub(MAGIC.len() as u64);
+        let version = read_bytes(&mut f, 1, &mut remaining)?[0];
+        if version != FORMAT_VERSION {
+            return Err(invalid(&format!("unsupported format version {version}")));
+        }
+        let header_len = read_u32(&mut f, &mut remaining)?;
+        let header_bytes = read_bytes(&mut f, header_len, &mut remaining)
+            .map_err(|_| invalid("header longer than the file"))?;
+        let header =
+            serde_json::from_slice(&header_bytes).map_err(|err| invalid(&err.to_string()))?;
+        let num_blocks = read_u32(&mut f, &mut remaining)?;
+        let mut blocks = Vec::new();
+        for _ in 0..num_blocks {
+            let offset = read_u32(&mut f, &mut remaining)?;
+            let len = read_u32(&mut f, &mut remaining)?;
+            let data = read_bytes(&mut f, len, &mut remaining)
+                .map_err(|_| invalid(&format!("block at 0x{offset:06x} longer than the file")))?;
+            blocks.push((offset, data));
+        }
+        let dump = RawDump { header, blocks };
+        if let Some((offset, data)) = dump.data_blocks().find(|(offset, data)| {
+            !(*offset as usize).is_multiple_of(CHUNK_SIZE) || !data.len().is_multiple_of(CHUNK_SIZE)
+        }) {
+            return Err(invalid(&format!(
+                "block at 0x{offset:06x} with length {} isn't made of {CHUNK_SIZE}-byte chunks",
+                data.len()
+            )));
+        }
+        Ok(dump)
+    }
+
+    /// First block read at `offset`
+    pub fn block_at(&self, offset: u32) -> Option<&[u8]> {
+        self.blocks
+            .iter()
+            .find(|(o, _)| *o == offset)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Blocks holding track data, i.e. without config, state probe and the blocks read while searching the end
+    pub fn data_blocks(&self) -> impl Iterator<Item = &(u32, Vec<u8>)> {
+        self.blocks
+            .iter()
+            .filter(|(o, _)| *o != CONFIG_POS && *o != STATE_PROBE_POS)
+            .skip(self.header.search_blocks)
+    }
+
+    /// All 128-byte chunks (header and 4 records) of the data blocks with their offset, see [`crate::gt120b_datadump::ChunkHeader`]
+    pub fn chunks(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.data_blocks().flat_map(|(offset, data)| {
+            data.chunks(CHUNK_SIZE)
+                .enumerate()
+                .map(move |(i, chunk)| (offset + (i * CHUNK_SIZE) as u32, chunk))
+        })
+    }
+}
+
+fn read_u32<R: Read>(f: &mut R, remaining: &mut u64) -> Result<u32> {
+    let buf = read_bytes(f, 4, remaining)?;
+    Ok(u32::from_le_bytes(buf.try_into().unwrap()))
+}
+
+/// Reads `len` bytes, but only if the file still has them, so a corrupt length can't allocate gigabytes
+fn read_bytes<R: Read>(f: &mut R, len: u32, remaining: &mut u64) -> Result<Vec<u8>> {
+    if len as u64 > *remaining {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    *remaining -= len as u64;
+    let mut buf = vec![0u8; len as usize];
+    f.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::sample_identification;
+
+    #[test]
+    fn save_load_roundtrip() {
+        let file_name = std::env::temp_dir()
+            .join(format!("igotu-raw-test-{}.bin", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let dump = RawDump {
+            header: RawDumpHeader {
+                identification: sample_identification(),
+                count_offset: 0x2000,
+                probe_state: Some("ffffffffffffffff".to_string()),
+                search_blocks: 1,
+            },
+            blocks: vec![
+                (CONFIG_POS, vec![0x10; 0xea]),
+                (STATE_PROBE_POS, vec![0xff; 8]),
+                (0x1000, vec![0x0c; 0x100]),
+                (0x1000, vec![0x0c; 0x100]),
+                (0x1100, vec![0x0c; 0xf00]),
+            ],
+        };
+
+        dump.save(&file_name).unwrap();
+        let loaded = RawDump::load(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert_eq!(loaded, dump);
+        assert_eq!(loaded.block_at(STATE_PROBE_POS), Some(&[0xffu8; 8][..]));
+        assert_eq!(loaded.data_blocks().count(), 2);
+    }
+
+    #[test]
+    fn load_invalid_magic() {
+        let file_name = std::env::temp_dir()
+            .join(format!("igotu-raw-test-invalid-{}.bin", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&file_name, b"<?xml version=\"1.0\"?>").unwrap();
+
+        let result = RawDump::load(&file_name);
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert!(matches!(result, Err(Error::InvalidRawDump(_))));
+    }
+
+    fn write_dump(name: &str, blocks: &[(u32, u32, &[u8])]) -> String {
+        let file_name = std::env::temp_dir()
+            .join(format!("igotu-raw-test-{name}-{}.bin", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let header = serde_json::to_vec(&RawDumpHeader {
+            identification: sample_identification(),
+            count_offset: 0x2000,
+            probe_state: None,
+            search_blocks: 0,
+        })
+        .unwrap();
+        let mut content = MAGIC.to_vec();
+        content.push(FORMAT_VERSION);
+        content.extend((header.len() as u32).to_le_bytes());
+        content.extend(header);
+        content.extend((blocks.len() as u32).to_le_bytes());
+        for (offset, len, data) in blocks {
+            content.extend(offset.to_le_bytes());
+            content.extend(len.to_le_bytes());
+            content.extend(*data);
+        }
+        std::fs::write(&file_name, content).unwrap();
+        file_name
+    }
+
+    #[test]
+    fn load_block_longer_than_file() {
+        let file_name = write_dump("long", &[(0x1000, 0xffff_fff0, &[0xff; CHUNK_SIZE])]);
+
+        let result = RawDump::load(&file_name);
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert!(
+            matches!(result, Err(Error::InvalidRawDump(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn load_block_not_chunk_aligned() {
+        let file_name = write_dump("unaligned", &[(0x1000, 100, &[0xff; 100])]);
+
+        let result = RawDump::load(&file_name);
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert!(
+            matches!(result, Err(Error::InvalidRawDump(_))),
+            "{result:?}"
+        );
+    }
+}
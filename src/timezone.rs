@@ -0,0 +1,199 @@
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// Principal locations of all timezones, from the tz database (public domain)
+const ZONE_TAB: &str = include_str!("../data/zone.tab");
+
+/// Timezone option for file names and day splitting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    /// Timezone of the computer
+    Local,
+    /// IANA name like `Europe/Zurich`
+    Named(Tz),
+    /// From the position of the first fix, see [`lookup`]
+    Auto,
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Timezone::Local),
+            "auto" => Ok(Timezone::Auto),
+            _ => s.parse().map(Timezone::Named).map_err(|_| {
+                format!("unknown timezone <{s}>, expected an IANA name, local or auto")
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timezone::Local => write!(f, "local"),
+            Timezone::Named(tz) => write!(f, "{tz}"),
+            Timezone::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl Timezone {
+    /// Replaces `Auto` by the timezone at the given position (degrees). Without position, UTC is used. The caller
+    /// should tell the user which zone was picked, see [`lookup`] for its limits
+    pub fn resolve(&self, position: Option<(f64, f64)>) -> Zone {
+        match self {
+            Timezone::Local => Zone::Local,
+            Timezone::Named(tz) => Zone::Tz(*tz),
+            Timezone::Auto => Zone::Tz(
+                position
+                    .map(|(lat, lon)| lookup(lat, lon))
+                    .unwrap_or(Tz::UTC),
+            ),
+        }
+    }
+}
+
+/// Resolved timezone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Local,
+    Tz(Tz),
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Zone::Local => write!(f, "local"),
+            Zone::Tz(tz) => write!(f, "{tz}"),
+        }
+    }
+}
+
+impl Zone {
+    pub fn naive_local(&self, time: &DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => time.with_timezone(&Local).naive_local(),
+            Zone::Tz(tz) => time.with_timezone(tz).naive_local(),
+        }
+    }
+}
+
+/**
+ Offline timezone lookup: the timezone with the nearest principal location in zone.tab.
+
+ This is an approximation without border data. It can be wrong near borders of timezones, e.g. Santiago de Compostela
+ gets `Europe/Lisbon` instead of `Europe/Madrid`. Use an explicit timezone name in such cases
+*/
+pub fn lookup(lat: f64, lon: f64) -> Tz {
+    ZONE_TAB
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+            let (_country, coordinates, name) = (columns.next()?, columns.next()?, columns.next()?);
+            let (zone_lat, zone_lon) = parse_iso6709(coordinates)?;
+            Some((
+                distance_rad(lat, lon, zone_lat, zone_lon),
+                name.parse::<Tz>().ok()?,
+            ))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, tz)| tz)
+        .unwrap_or(Tz::UTC)
+}
+
+/// `+DDMM+DDDMM` or `+DDMMSS+DDDMMSS`
+fn parse_iso6709(s: &str) -> Option<(f64, f64)> {
+    let split = s[1..].find(['+', '-'])? + 1;
+    Some((parse_dms(&s[..split], 2)?, parse_dms(&s[split..], 3)?))
+}
+
+fn parse_dms(s: &str, degree_digits: usize) -> Option<f64> {
+    let sign = if s.starts_with('-') { -1.0 } else { 1.0 };
+    let digits = &s[1..];
+    let part = |range: std::ops::Range<usize>| -> Option<f64> {
+        digits.get(range).map_or(Some(0.0), |d| d.parse().ok())
+    };
+    let degrees = part(0..degree_digits)?;
+    let minutes = part(degree_digits..degree_digits + 2)?;
+    let seconds = part(degree_digits + 2..degree_digits + 4)?;
+    Some(sign * (degrees + minutes / 60.0 + seconds / 3600.0))
+}
+
+/// Central angle between two positions, enough for comparing distances
+fn distance_rad(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::utc_dt_from_ymd_hms_milli;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("local".parse(), Ok(Timezone::Local));
+        assert_eq!("auto".parse(), Ok(Timezone::Auto));
+        assert_eq!(
+            "Europe/Zurich".parse(),
+            Ok(Timezone::Named(Tz::Europe__Zurich))
+        );
+        assert!("Mars/Olympus_Mons".parse::<Timezone>().is_err());
+    }
+
+    #[test]
+    fn iso6709() {
+        assert_eq!(
+            parse_iso6709("+4723+00832"),
+            Some((47.0 + 23.0 / 60.0, 8.0 + 32.0 / 60.0))
+        );
+        assert_eq!(
+            parse_iso6709("-3352+15113"),
+            Some((-(33.0 + 52.0 / 60.0), 151.0 + 13.0 / 60.0))
+        );
+        assert_eq!(
+            parse_iso6709("+404251-0740023"),
+            Some((
+                40.0 + 42.0 / 60.0 + 51.0 / 3600.0,
+                -(74.0 + 0.0 / 60.0 + 23.0 / 3600.0)
+            ))
+        );
+    }
+
+    #[test]
+    fn lookup_positions() {
+        assert_eq!(lookup(47.366686, 8.548398), Tz::Europe__Zurich);
+        assert_eq!(lookup(40.7, -74.0), Tz::America__New_York);
+        assert_eq!(lookup(-33.9, 151.2), Tz::Australia__Sydney);
+        assert_eq!(lookup(35.7, 139.7), Tz::Asia__Tokyo);
+        // known limit without border data: Santiago de Compostela is nearer to Lisbon than to Madrid
+        assert_eq!(lookup(42.88, -8.54), Tz::Europe__Lisbon);
+    }
+
+    #[test]
+    fn resolve_without_position() {
+        assert_eq!(Timezone::Auto.resolve(None), Zone::Tz(Tz::UTC));
+        assert_eq!(
+            Timezone::Auto.resolve(Some((47.37, 8.55))),
+            Zone::Tz(Tz::Europe__Zurich)
+        );
+    }
+
+    #[test]
+    fn naive_local() {
+        let time = utc_dt_from_ymd_hms_milli(2025, 7, 31, 22, 30, 0, 0);
+
+        let zurich = Zone::Tz(Tz::Europe__Zurich).naive_local(&time);
+
+        // CEST, already the next day
+        assert_eq!(zurich.to_string(), "2025-08-01 00:30:00");
+    }
+}
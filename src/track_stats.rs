@@ -0,0 +1,268 @@
+use crate::error::Result;
+use crate::file_name::OutputFile;
+use crate::gt120b_datadump::Track;
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+/// Between two points slower than this (m/s, from distance and time), the logger is counted as not moving
+const MIN_MOVING_SPEED: f64 = 0.5;
+
+/**
+ Statistics of one track, or of all tracks together.
+
+ Distances are haversine distances between the points, speeds in m/s. `max_speed_ms` is the highest speed reported by
+ the device, `avg_speed_ms` the distance divided by the moving time. Elevation gain and loss are the plain sums of the
+ differences between the points, without smoothing
+*/
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TrackStats {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_s: i64,
+    pub moving_time_s: i64,
+    pub distance_m: f64,
+    pub max_speed_ms: f32,
+    pub avg_speed_ms: f64,
+    pub ele_gain_m: f64,
+    pub ele_loss_m: f64,
+    pub hdop_min: f32,
+    pub hdop_max: f32,
+    pub points: usize,
+    pub button_presses: usize,
+}
+
+impl TrackStats {
+    pub fn from_track(track: &Track) -> Self {
+        let mut moving_time_s = 0;
+        let (mut ele_gain_m, mut ele_loss_m) = (0.0, 0.0);
+        for w in track.segments.iter().flat_map(|segment| segment.windows(2)) {
+            let secs = (w[1].time - w[0].time).num_milliseconds() as f64 / 1000.0;
+            if secs > 0.0 && w[0].distance_to(w[1]) / secs >= MIN_MOVING_SPEED {
+                moving_time_s += (w[1].time - w[0].time).num_seconds();
+            }
+            let climb = w[1].ele() - w[0].ele();
+            if climb > 0.0 {
+                ele_gain_m += climb;
+            } else {
+                ele_loss_m -= climb;
+            }
+        }
+        let distance_m = track.distance_m();
+        TrackStats {
+            start: track.start_time(),
+            end: track.end_time(),
+            duration_s: (track.end_time() - track.start_time()).num_seconds(),
+            moving_time_s,
+            distance_m,
+            max_speed_ms: track.points().map(|wpt| wpt.speed).fold(0.0, f32::max),
+            avg_speed_ms: avg_speed(distance_m, moving_time_s),
+            ele_gain_m,
+            ele_loss_m,
+            hdop_min: track.points().map(|wpt| wpt.hdop).fold(f32::MAX, f32::min),
+            hdop_max: track.points().map(|wpt| wpt.hdop).fold(0.0, f32::max),
+            points: track.points().count(),
+            button_presses: track.trigger_times.len(),
+        }
+    }
+
+    /// Sum of several tracks. Durations are added, so the time between the tracks isn't counted
+    fn total(tracks: &[TrackStats]) -> Option<Self> {
+        let first = tracks.first()?;
+        let mut total = first.clone();
+        for stats in &tracks[1..] {
+            total.start = total.start.min(stats.start);
+            total.end = total.end.max(stats.end);
+            total.duration_s += stats.duration_s;
+            total.moving_time_s += stats.moving_time_s;
+            total.distance_m += stats.distance_m;
+            total.max_speed_ms = total.max_speed_ms.max(stats.max_speed_ms);
+            total.ele_gain_m += stats.ele_gain_m;
+            total.ele_loss_m += stats.ele_loss_m;
+            total.hdop_min = total.hdop_min.min(stats.hdop_min);
+            total.hdop_max = total.hdop_max.max(stats.hdop_max);
+            total.points += stats.points;
+            total.button_presses += stats.button_presses;
+        }
+        total.avg_speed_ms = avg_speed(total.distance_m, total.moving_time_s);
+        Some(total)
+    }
+}
+
+fn avg_speed(distance_m: f64, moving_time_s: i64) -> f64 {
+    if moving_time_s > 0 {
+        distance_m / moving_time_s as f64
+    } else {
+        0.0
+    }
+}
+
+/**
+ Statistics of all tracks of a download, like `helpers/bin/gpx_summary.py` prints them for gpx files.
+ [`fmt::Display`] gives a table, the JSON file has the same values with the units in the field names
+*/
+#[derive(Serialize, Debug)]
+pub struct Summary {
+    pub tracks: Vec<TrackStats>,
+    pub total: Option<TrackStats>,
+}
+
+impl Summary {
+    pub fn new(tracks: &[Track]) -> Self {
+        let tracks: Vec<TrackStats> = tracks.iter().map(TrackStats::from_track).collect();
+        let total = TrackStats::total(&tracks);
+        Summary { tracks, total }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        debug!("Writing statistics {}", path.display());
+        let content = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        let mut f = OutputFile::create(path)?;
+        f.write_all(content.as_bytes())?;
+        f.commit()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<6} {:<20} {:<20} {:>8} {:>8} {:>9} {:>9} {:>9} {:>6} {:>6} {:>9} {:>6} {:>7}",
+            "Track",
+            "Start (UTC)",
+            "End (UTC)",
+            "Duration",
+            "Moving",
+            "Distance",
+            "Max",
+            "Avg",
+            "Gain",
+            "Loss",
+            "HDOP",
+            "Points",
+            "Buttons"
+        )?;
+        let rows = self
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, s)| ((i + 1).to_string(), s));
+        for (name, stats) in rows.chain(self.total.iter().map(|s| ("Total".to_string(), s))) {
+            writeln!(
+                f,
+                "{:<6} {:<20} {:<20} {:>8} {:>8} {:>6.2} km {:>4.1} km/h {:>4.1} km/h {:>4.0} m {:>4.0} m {:>4.1}-{:<4.1} {:>6} {:>7}",
+                name,
+                stats.start.format("%Y-%m-%d %H:%M:%S"),
+                stats.end.format("%Y-%m-%d %H:%M:%S"),
+                format_duration(stats.duration_s),
+                format_duration(stats.moving_time_s),
+                stats.distance_m / 1000.0,
+                stats.max_speed_ms as f64 * 3.6,
+                stats.avg_speed_ms * 3.6,
+                stats.ele_gain_m,
+                stats.ele_loss_m,
+                stats.hdop_min,
+                stats.hdop_max,
+                stats.points,
+                stats.button_presses
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn format_duration(secs: i64) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gt120b_datadump::{Waypoint, test_waypoint, utc_dt_from_ymd_hms_milli};
+
+    #[test]
+    fn summary_of_tracks() {
+        // 0.0001 degrees latitude are 11.12 m. Moving 10 s, standing 20 s, moving 10 s
+        let wpts = [
+            Waypoint {
+                lat_e7: 473660000,
+                ele_cm: 40000,
+                speed: 0.0,
+                hdop: 1.2,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 0, 0))
+            },
+            Waypoint {
+                lat_e7: 473661000,
+                ele_cm: 40500,
+                speed: 1.1,
+                hdop: 0.9,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 10, 0))
+            },
+            Waypoint {
+                lat_e7: 473661000,
+                ele_cm: 40300,
+                speed: 0.0,
+                hdop: 2.5,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 30, 0))
+            },
+            Waypoint {
+                lat_e7: 473662000,
+                ele_cm: 40100,
+                speed: 1.3,
+                hdop: 1.0,
+                ..test_waypoint(utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 40, 0))
+            },
+        ];
+        let tracks = [
+            Track {
+                segments: vec![wpts.iter().collect()],
+                trigger_times: vec![utc_dt_from_ymd_hms_milli(2025, 7, 31, 20, 8, 5, 0)],
+                switch_on: None,
+                switch_off: None,
+            },
+            Track {
+                segments: vec![vec![&wpts[0]]],
+                trigger_times: vec![],
+                switch_on: None,
+                switch_off: None,
+            },
+        ];
+
+        let summary = Summary::new(&tracks);
+
+        let stats = &summary.tracks[0];
+        assert_eq!(stats.duration_s, 40);
+        assert_eq!(stats.moving_time_s, 20);
+        assert!(
+            (stats.distance_m - 22.24).abs() < 0.01,
+            "{}",
+            stats.distance_m
+        );
+        assert!((stats.avg_speed_ms - 1.112).abs() < 0.001);
+        assert_eq!(stats.max_speed_ms, 1.3);
+        assert!((stats.ele_gain_m - 5.0).abs() < 1e-9);
+        assert!((stats.ele_loss_m - 4.0).abs() < 1e-9);
+        assert_eq!((stats.hdop_min, stats.hdop_max), (0.9, 2.5));
+        assert_eq!((stats.points, stats.button_presses), (4, 1));
+
+        let total = summary.total.as_ref().unwrap();
+        assert_eq!(total.points, 5);
+        assert_eq!(total.duration_s, 40);
+        assert_eq!(total.moving_time_s, 20);
+        assert_eq!(total.distance_m, stats.distance_m);
+
+        let table = summary.to_string();
+        assert_eq!(table.lines().count(), 4);
+        assert!(
+            table.lines().nth(1).unwrap().starts_with(
+                "1      2025-07-31 20:08:00  2025-07-31 20:08:40   0:00:40  0:00:20   0.02 km  4.7 km/h  4.0 km/h"
+            ),
+            "{table}"
+        );
+        assert!(table.lines().nth(3).unwrap().starts_with("Total "));
+    }
+}
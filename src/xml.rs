@@ -0,0 +1,21 @@
+/// Escapes the characters with a special meaning in XML text and attribute values (in double quotes)
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_escape_special_chars() {
+        assert_eq!(
+            xml_escape(r#"<a href="x">R&B</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;R&amp;B&lt;/a&gt;"
+        );
+        assert_eq!(xml_escape("Rider 1"), "Rider 1");
+    }
+}